@@ -0,0 +1,42 @@
+use std::net::TcpStream;
+use tokio::{io::AsyncReadExt, task::LocalSet};
+use tokio_libssh2::{auth, Session};
+
+fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
+
+    let mut rt = tokio::runtime::Runtime::new()?;
+    let local = LocalSet::new();
+    local.block_on(&mut rt, main_local())?;
+
+    Ok(())
+}
+
+async fn main_local() -> anyhow::Result<()> {
+    let session = Session::new()?;
+
+    let stream = TcpStream::connect("127.0.0.1:22")?;
+    let session = session.handshake(stream).await?;
+
+    let session = session
+        .authenticate("testuser", auth::password("testuser"))
+        .await
+        .map_err(|err| err.into_error())?;
+
+    let mut channel = session.open_channel_session().await?;
+    channel.request_auth_agent().await?;
+    channel.exec("ssh-add -l").await?;
+
+    // The connections to the forwarded agent are bridged to `SSH_AUTH_SOCK`.
+    let agent = channel.auth_agent_channels().forward_to_agent(None);
+    let mut buf = String::new();
+    let (forwarded, read) = futures::join!(agent, channel.read_to_string(&mut buf));
+    forwarded?;
+    read?;
+    println!("stdout:\n{}", buf);
+
+    let status = channel.wait().await?;
+    println!("exit status: {}", status);
+
+    Ok(())
+}
@@ -0,0 +1,50 @@
+use std::{net::TcpStream, time::Duration};
+use tokio::task::LocalSet;
+use tokio_libssh2::{auth, expect::Expect, Session};
+
+fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
+
+    let mut rt = tokio::runtime::Runtime::new()?;
+    let local = LocalSet::new();
+    local.block_on(&mut rt, main_local())?;
+
+    Ok(())
+}
+
+async fn main_local() -> anyhow::Result<()> {
+    let session = Session::new()?;
+
+    let stream = TcpStream::connect("127.0.0.1:22")?;
+    let session = session.handshake(stream).await?;
+
+    let session = session
+        .authenticate("testuser", auth::password("testuser"))
+        .await
+        .map_err(|err| err.into_error())?;
+
+    let mut channel = session.open_channel_session().await?;
+    channel.request_pty("vt100", None, None).await?;
+    channel.shell().await?;
+
+    let mut expect = Expect::new(&channel).timeout(Duration::from_secs(10));
+    let prompt = expect.detect_prompt().await?;
+    println!("detected prompt: {:?}", prompt);
+
+    let output = expect.run("uname -a").await?;
+    println!("uname -a:\n{}", output);
+
+    expect
+        .send_line("read -p 'name? ' name; echo \"hi, $name\"")
+        .await?;
+    expect.expect("name? ").await?;
+    expect.send_line("testuser").await?;
+    let m = expect.expect_prompt().await?;
+    println!("greeting:\n{}", m.before);
+
+    expect.send_line("exit").await?;
+    let status = channel.wait().await?;
+    println!("exit status: {}", status);
+
+    Ok(())
+}
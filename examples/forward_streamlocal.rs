@@ -0,0 +1,32 @@
+use std::net::TcpStream;
+use tokio::task::LocalSet;
+use tokio_libssh2::{auth, forward::LocalForwarder, Session};
+
+fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
+
+    let mut rt = tokio::runtime::Runtime::new()?;
+    let local = LocalSet::new();
+    local.block_on(&mut rt, main_local())?;
+
+    Ok(())
+}
+
+async fn main_local() -> anyhow::Result<()> {
+    let session = Session::new()?;
+
+    let stream = TcpStream::connect("127.0.0.1:22")?;
+    let session = session.handshake(stream).await?;
+
+    let session = session
+        .authenticate("testuser", auth::password("testuser"))
+        .await
+        .map_err(|err| err.into_error())?;
+
+    let forwarder =
+        LocalForwarder::bind_streamlocal("127.0.0.1:2375", "/var/run/docker.sock").await?;
+    println!("listening on {}", forwarder.local_addr()?);
+    forwarder.run(&session).await?;
+
+    Ok(())
+}
@@ -1,5 +1,5 @@
 use std::net::TcpStream;
-use tokio::{io::AsyncReadExt, task::LocalSet};
+use tokio::task::LocalSet;
 use tokio_libssh2::{auth, Session};
 
 fn main() -> anyhow::Result<()> {
@@ -13,24 +13,19 @@ fn main() -> anyhow::Result<()> {
 }
 
 async fn main_local() -> anyhow::Result<()> {
-    let mut session = Session::new()?;
+    let session = Session::new()?;
 
     let stream = TcpStream::connect("127.0.0.1:22")?;
-    session.handshake(stream).await?;
+    let session = session.handshake(stream).await?;
 
-    session
+    let session = session
         .authenticate("testuser", auth::password("testuser"))
-        .await?;
+        .await
+        .map_err(|err| err.into_error())?;
 
-    let mut channel = session.open_channel_session().await?;
-    channel.exec("env").await?;
-
-    let mut buf = String::new();
-    channel.read_to_string(&mut buf).await?;
-    println!("stdout:\n{}", buf);
-
-    let status = channel.exit_status()?;
-    println!("exit status: {}", status);
+    let output = session.command("env").output().await?;
+    println!("stdout:\n{}", String::from_utf8_lossy(&output.stdout));
+    println!("exit status: {}", output.status);
 
     Ok(())
 }
@@ -0,0 +1,37 @@
+use std::net::TcpStream;
+use tokio::task::LocalSet;
+use tokio_libssh2::{auth, terminal, Session};
+
+fn main() -> anyhow::Result<()> {
+    let mut rt = tokio::runtime::Runtime::new()?;
+    let local = LocalSet::new();
+    let code = local.block_on(&mut rt, main_local())?;
+
+    // The runtime is shut down before exiting, so that the terminal modes
+    // are restored and the pending tasks are dropped.
+    drop(local);
+    drop(rt);
+    std::process::exit(code);
+}
+
+async fn main_local() -> anyhow::Result<i32> {
+    let session = Session::new()?;
+
+    let stream = TcpStream::connect("127.0.0.1:22")?;
+    let session = session.handshake(stream).await?;
+
+    let session = session
+        .authenticate("testuser", auth::password("testuser"))
+        .await
+        .map_err(|err| err.into_error())?;
+
+    let term = std::env::var("TERM").unwrap_or_else(|_| "xterm".into());
+    let size = terminal::size(libc::STDOUT_FILENO).ok();
+
+    let mut channel = session.open_channel_session().await?;
+    channel.request_pty(&term, None, size).await?;
+    channel.shell().await?;
+
+    let status = terminal::interact(&mut channel).await?;
+    Ok(status.code().unwrap_or(255))
+}
@@ -13,10 +13,10 @@ fn main() -> anyhow::Result<()> {
 }
 
 async fn main_local() -> anyhow::Result<()> {
-    let mut session = Session::new()?;
+    let session = Session::new()?;
 
     let stream = TcpStream::connect("127.0.0.1:22")?;
-    session.handshake(stream).await?;
+    let mut session = session.handshake(stream).await?;
 
     let list = session.list_userauth("testuser").await?;
     println!("listed userauth: {:?}", std::str::from_utf8(&list));
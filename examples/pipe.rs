@@ -0,0 +1,39 @@
+use std::net::TcpStream;
+use tokio::task::LocalSet;
+use tokio_libssh2::{auth, Session};
+
+fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
+
+    let mut rt = tokio::runtime::Runtime::new()?;
+    let local = LocalSet::new();
+    local.block_on(&mut rt, main_local())?;
+
+    Ok(())
+}
+
+async fn main_local() -> anyhow::Result<()> {
+    let session = Session::new()?;
+
+    let stream = TcpStream::connect("127.0.0.1:22")?;
+    let session = session.handshake(stream).await?;
+
+    let session = session
+        .authenticate("testuser", auth::password("testuser"))
+        .await
+        .map_err(|err| err.into_error())?;
+
+    let mut channel = session.open_channel_session().await?;
+    channel.exec("gzip -c").await?;
+
+    let status = channel
+        .pipe(tokio::io::stdin(), tokio::io::stdout(), tokio::io::stderr())
+        .await?;
+    eprintln!(
+        "sent {} bytes, received {} bytes",
+        status.stdin_bytes, status.stdout_bytes
+    );
+    eprintln!("exit status: {}", status.status);
+
+    Ok(())
+}
@@ -13,14 +13,15 @@ fn main() -> anyhow::Result<()> {
 }
 
 async fn main_local() -> anyhow::Result<()> {
-    let mut session = Session::new()?;
+    let session = Session::new()?;
 
     let stream = TcpStream::connect("127.0.0.1:22")?;
-    session.handshake(stream).await?;
+    let session = session.handshake(stream).await?;
 
-    session
+    let session = session
         .authenticate("testuser", auth::password("testuser"))
-        .await?;
+        .await
+        .map_err(|err| err.into_error())?;
 
     let mut sftp = session.sftp().await?;
 
@@ -0,0 +1,44 @@
+use std::net::TcpStream;
+use tokio::task::LocalSet;
+use tokio_libssh2::{auth, Session};
+
+fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
+
+    let mut rt = tokio::runtime::Runtime::new()?;
+    let local = LocalSet::new();
+    local.block_on(&mut rt, main_local())?;
+
+    Ok(())
+}
+
+async fn main_local() -> anyhow::Result<()> {
+    let session = Session::new()?;
+
+    let stream = TcpStream::connect("127.0.0.1:22")?;
+    let session = session.handshake(stream).await?;
+
+    let session = session
+        .authenticate("testuser", auth::password("testuser"))
+        .await
+        .map_err(|err| err.into_error())?;
+
+    let mut shell = session.open_shell().await?;
+    for command in &[
+        "cd /tmp",
+        "export GREETING=hello",
+        "pwd; echo $GREETING",
+        "ls /nonexistent",
+    ] {
+        let output = shell.run(command).await?;
+        println!("$ {}", command);
+        print!("{}", String::from_utf8_lossy(&output.stdout));
+        eprint!("{}", String::from_utf8_lossy(&output.stderr));
+        println!("(exit code: {})", output.code);
+    }
+
+    let status = shell.exit().await?;
+    println!("exit status: {}", status);
+
+    Ok(())
+}
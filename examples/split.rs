@@ -0,0 +1,54 @@
+use std::net::TcpStream;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    task::LocalSet,
+};
+use tokio_libssh2::{auth, Session};
+
+fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
+
+    let mut rt = tokio::runtime::Runtime::new()?;
+    let local = LocalSet::new();
+    local.block_on(&mut rt, main_local())?;
+
+    Ok(())
+}
+
+async fn main_local() -> anyhow::Result<()> {
+    let session = Session::new()?;
+
+    let stream = TcpStream::connect("127.0.0.1:22")?;
+    let session = session.handshake(stream).await?;
+
+    let session = session
+        .authenticate("testuser", auth::password("testuser"))
+        .await
+        .map_err(|err| err.into_error())?;
+
+    let mut channel = session.open_channel_session().await?;
+    channel.exec("cat; echo done >&2").await?;
+
+    let (mut stdin, mut stdout, mut stderr) = channel.split();
+    let input = "hello\n".repeat(100_000);
+
+    let write = async {
+        stdin.write_all(input.as_bytes()).await?;
+        stdin.shutdown().await
+    };
+    let mut out = Vec::new();
+    let mut err = Vec::new();
+    let (written, out_len, err_len) = futures::join!(
+        write,
+        stdout.read_to_end(&mut out),
+        stderr.read_to_end(&mut err),
+    );
+    written?;
+    println!("stdout: {} bytes", out_len?);
+    println!("stderr: {} bytes", err_len?);
+
+    let status = channel.wait().await?;
+    println!("exit status: {}", status);
+
+    Ok(())
+}
@@ -0,0 +1,69 @@
+use anyhow::Context as _;
+use std::{net::TcpStream, process::Command};
+use tokio::{io::AsyncReadExt, task::LocalSet};
+use tokio_libssh2::{auth, Session};
+
+fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
+
+    let mut rt = tokio::runtime::Runtime::new()?;
+    let local = LocalSet::new();
+    local.block_on(&mut rt, main_local())?;
+
+    Ok(())
+}
+
+async fn main_local() -> anyhow::Result<()> {
+    let session = Session::new()?;
+
+    let stream = TcpStream::connect("127.0.0.1:22")?;
+    let session = session.handshake(stream).await?;
+
+    let session = session
+        .authenticate("testuser", auth::password("testuser"))
+        .await
+        .map_err(|err| err.into_error())?;
+
+    // Forward the cookie of the local display, which is checked by the
+    // local X server on the forwarded connections.
+    let (auth_proto, auth_cookie) = local_cookie()?;
+
+    let mut channel = session.open_channel_session().await?;
+    channel
+        .request_x11(false, Some(&auth_proto), Some(&auth_cookie), 0)
+        .await?;
+    channel.exec("xeyes").await?;
+
+    let x11 = channel.x11_channels().forward_to_display(None);
+    let mut buf = String::new();
+    let (forwarded, read) = futures::join!(x11, channel.read_to_string(&mut buf));
+    forwarded?;
+    read?;
+    println!("stdout:\n{}", buf);
+
+    let status = channel.wait().await?;
+    println!("exit status: {}", status);
+
+    Ok(())
+}
+
+/// Read the authentication protocol and the hexadecimal cookie of the local
+/// display with `xauth list $DISPLAY`.
+fn local_cookie() -> anyhow::Result<(String, String)> {
+    let display = std::env::var("DISPLAY").context("the DISPLAY variable is not set")?;
+    let output = Command::new("xauth").arg("list").arg(&display).output()?;
+    anyhow::ensure!(output.status.success(), "xauth failed: {}", output.status);
+
+    // Each line is of the form `host/unix:0  MIT-MAGIC-COOKIE-1  <hex>`.
+    let stdout = String::from_utf8(output.stdout)?;
+    let mut fields = stdout
+        .lines()
+        .next()
+        .context("no cookie for the display")?
+        .split_whitespace()
+        .skip(1);
+    match (fields.next(), fields.next()) {
+        (Some(proto), Some(cookie)) => Ok((proto.to_owned(), cookie.to_owned())),
+        _ => anyhow::bail!("unexpected output from xauth: {}", stdout),
+    }
+}
@@ -1,6 +1,9 @@
 //! Authentication of a session.
 
-use crate::{error::Result, session::Session};
+use crate::{
+    error::Result,
+    session::{BlockTimer, SessionInner},
+};
 use futures::task::{self, Poll};
 use libssh2_sys as sys;
 use std::pin::Pin;
@@ -14,8 +17,9 @@ pub trait Authenticator {
 }
 
 pub struct AuthContext<'auth> {
-    pub(crate) sess: &'auth mut Session,
+    pub(crate) sess: &'auth mut SessionInner,
     pub(crate) username: &'auth str,
+    pub(crate) timer: &'auth BlockTimer,
 }
 
 /// An `Authenticator` using the password.
@@ -31,7 +35,7 @@ impl<T: AsRef<str>> Authenticator for PasswordAuth<T> {
     ) -> Poll<Result<()>> {
         let username = auth.username;
         let password = self.password.as_ref();
-        auth.sess.poll_with(cx, |sess| {
+        auth.sess.poll_with(cx, auth.timer, |sess| {
             let rc = unsafe {
                 sys::libssh2_userauth_password_ex(
                     sess.as_raw_ptr(),
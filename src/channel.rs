@@ -1,24 +1,59 @@
-use crate::{error::Result, session::Session};
+use crate::{
+    error::{Result, Ssh2Error},
+    ffi,
+    instrument::instrument,
+    lines::Lines,
+    pty::{PtySize, TerminalModes},
+    session::{BlockTimer, SessionInner},
+    stats::Stats,
+};
 use futures::{
     future::poll_fn,
     task::{self, Poll},
 };
 use libssh2_sys as sys;
 use std::{
-    io,
+    cell::{Cell, RefCell},
+    ffi::CString,
+    fmt, io,
     pin::Pin,
     ptr::{self, NonNull},
+    rc::Rc,
 };
-use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// The stream ID of the standard error.
+const SSH_EXTENDED_DATA_STDERR: i32 = 1;
 
 /// A portion of an SSH connection on which data can be read and written.
-pub struct Channel<'sess> {
+pub struct Channel {
+    inner: Rc<ChannelInner>,
+    read_timer: BlockTimer,
+    write_timer: BlockTimer,
+}
+
+/// The state of a channel shared with its halves.
+struct ChannelInner {
     raw: NonNull<sys::LIBSSH2_CHANNEL>,
-    sess: &'sess mut Session,
+    id: u64,
+    sess: Rc<RefCell<SessionInner>>,
+    stats: Cell<Stats>,
+    closed: Cell<bool>,
+    kill_on_drop: RefCell<Option<String>>,
 }
 
-impl Drop for Channel<'_> {
+impl Drop for ChannelInner {
     fn drop(&mut self) {
+        if let Some(signal) = self.kill_on_drop.get_mut().take() {
+            if !self.closed.get() {
+                tracing::debug!(channel_id = self.id, "send {} on drop", signal);
+                // The channel is freed by the session after the signal is
+                // sent and the channel is closed.
+                self.sess.borrow_mut().kill_on_drop(self.raw, signal);
+                return;
+            }
+        }
+
         unsafe {
             // FIXME: should we handle EAGAIN at here?
             sys::libssh2_channel_free(self.raw.as_ptr());
@@ -26,28 +61,53 @@ impl Drop for Channel<'_> {
     }
 }
 
-impl<'sess> Channel<'sess> {
-    pub(crate) fn new(raw: NonNull<sys::LIBSSH2_CHANNEL>, sess: &'sess mut Session) -> Self {
-        Self { raw, sess }
+impl Channel {
+    pub(crate) fn new(
+        raw: NonNull<sys::LIBSSH2_CHANNEL>,
+        id: u64,
+        sess: Rc<RefCell<SessionInner>>,
+    ) -> Self {
+        Self {
+            inner: Rc::new(ChannelInner {
+                raw,
+                id,
+                sess,
+                stats: Cell::default(),
+                closed: Cell::new(false),
+                kill_on_drop: RefCell::new(None),
+            }),
+            read_timer: BlockTimer::default(),
+            write_timer: BlockTimer::default(),
+        }
     }
 
     /// Set an environment variable in the remote channel's process space.
     pub async fn setenv<'a>(&'a mut self, name: &'a str, value: &'a str) -> Result<()> {
-        poll_fn(|cx| {
-            let channel = &mut self.raw;
-            self.sess.poll_with(cx, |sess| {
-                sess.rc(unsafe {
-                    sys::libssh2_channel_setenv_ex(
-                        channel.as_mut(),
-                        name.as_ptr() as *const libc::c_char,
-                        name.len() as libc::c_uint,
-                        value.as_ptr() as *const libc::c_char,
-                        value.len() as libc::c_uint,
-                    )
+        let span = op_span!(
+            self.inner.sess.borrow(),
+            "setenv",
+            channel_id = self.inner.id,
+            name
+        );
+        let timer = BlockTimer::default();
+        instrument(
+            span,
+            poll_fn(|cx| {
+                let channel = self.inner.raw;
+                self.inner.poll_with(cx, &timer, |sess| {
+                    sess.rc(unsafe {
+                        sys::libssh2_channel_setenv_ex(
+                            channel.as_ptr(),
+                            name.as_ptr() as *const libc::c_char,
+                            name.len() as libc::c_uint,
+                            value.as_ptr() as *const libc::c_char,
+                            value.len() as libc::c_uint,
+                        )
+                    })
+                    .map(drop)
                 })
-                .map(drop)
-            })
-        })
+            }),
+        )
         .await
     }
 
@@ -57,28 +117,84 @@ impl<'sess> Channel<'sess> {
         request: &'a str,
         message: Option<&'a str>,
     ) -> Result<()> {
-        poll_fn(|cx| {
-            let channel = &mut self.raw;
-            let (msg, msg_len) = match message {
-                Some(msg) => (msg.as_ptr(), msg.len()),
-                None => (ptr::null(), 0),
-            };
-            self.sess.poll_with(cx, |sess| {
-                sess.rc(unsafe {
-                    sys::libssh2_channel_process_startup(
-                        channel.as_mut(),
-                        request.as_ptr() as *const libc::c_char,
-                        request.len() as libc::c_uint,
-                        msg as *const libc::c_char,
-                        msg_len as libc::c_uint,
-                    )
+        let span = op_span!(
+            self.inner.sess.borrow(),
+            "process_startup",
+            channel_id = self.inner.id,
+            request,
+            message
+        );
+        let timer = BlockTimer::default();
+        instrument(
+            span,
+            poll_fn(|cx| {
+                let channel = self.inner.raw;
+                let (msg, msg_len) = match message {
+                    Some(msg) => (msg.as_ptr(), msg.len()),
+                    None => (ptr::null(), 0),
+                };
+                self.inner.poll_with(cx, &timer, |sess| {
+                    sess.rc(unsafe {
+                        sys::libssh2_channel_process_startup(
+                            channel.as_ptr(),
+                            request.as_ptr() as *const libc::c_char,
+                            request.len() as libc::c_uint,
+                            msg as *const libc::c_char,
+                            msg_len as libc::c_uint,
+                        )
+                    })
+                    .map(drop)
                 })
-                .map(drop)
-            })
-        })
+            }),
+        )
         .await
     }
 
+    /// Send a signal to the remote process, e.g. `TERM`, `INT` or `KILL`.
+    ///
+    /// The name of the signal is given without the `SIG` prefix, which is
+    /// stripped if specified. The remote host does not reply to the signal
+    /// request, so this method returns as soon as the request is sent.
+    pub async fn signal(&mut self, name: &str) -> Result<()> {
+        let name = signal_name(name);
+        let span = op_span!(
+            self.inner.sess.borrow(),
+            "signal",
+            channel_id = self.inner.id,
+            name
+        );
+        let timer = BlockTimer::default();
+        instrument(
+            span,
+            poll_fn(|cx| {
+                let channel = self.inner.raw;
+                self.inner.poll_with(cx, &timer, |sess| {
+                    sess.rc(unsafe {
+                        ffi::libssh2_channel_signal_ex(
+                            channel.as_ptr(),
+                            name.as_ptr() as *const libc::c_char,
+                            name.len(),
+                        )
+                    })
+                    .map(drop)
+                })
+            }),
+        )
+        .await
+    }
+
+    /// Send the signal to the remote process when the channel is dropped
+    /// before the remote host closes it.
+    ///
+    /// The signal is sent without waiting for the reply, and then the
+    /// channel is closed. They are queued in the session and sent without
+    /// blocking, so the rest is sent when the session is polled by another
+    /// operation. Since the channel is shared with the halves returned from
+    /// `split`, the signal is queued after all of them are dropped.
+    pub fn set_kill_on_drop(&mut self, signal: Option<&str>) {
+        *self.inner.kill_on_drop.borrow_mut() = signal.map(|name| signal_name(name).to_owned());
+    }
+
     /// Start a shell.
     pub async fn shell(&mut self) -> Result<()> {
         self.process_startup("shell", None).await
@@ -94,80 +210,835 @@ impl<'sess> Channel<'sess> {
         self.process_startup("subsystem", Some(subsystem)).await
     }
 
+    /// Request a pseudo-terminal with the specified terminal type.
+    ///
+    /// If the terminal modes or the dimensions are omitted, an empty set of
+    /// modes and the 80x24 terminal are requested respectively.
+    pub async fn request_pty<'a>(
+        &'a mut self,
+        term: &'a str,
+        modes: Option<&'a TerminalModes>,
+        size: Option<PtySize>,
+    ) -> Result<()> {
+        let span = op_span!(
+            self.inner.sess.borrow(),
+            "request_pty",
+            channel_id = self.inner.id,
+            term
+        );
+        // The empty set of modes is still terminated with `TTY_OP_END`.
+        let modes = modes.map_or_else(|| TerminalModes::new().to_bytes(), TerminalModes::to_bytes);
+        let size = size.unwrap_or_default();
+        let timer = BlockTimer::default();
+        instrument(
+            span,
+            poll_fn(|cx| {
+                let channel = self.inner.raw;
+                self.inner.poll_with(cx, &timer, |sess| {
+                    sess.rc(unsafe {
+                        sys::libssh2_channel_request_pty_ex(
+                            channel.as_ptr(),
+                            term.as_ptr() as *const libc::c_char,
+                            term.len() as libc::c_uint,
+                            modes.as_ptr() as *const libc::c_char,
+                            modes.len() as libc::c_uint,
+                            size.cols as libc::c_int,
+                            size.rows as libc::c_int,
+                            size.pixel_width as libc::c_int,
+                            size.pixel_height as libc::c_int,
+                        )
+                    })
+                    .map(drop)
+                })
+            }),
+        )
+        .await
+    }
+
+    /// Request the X11 forwarding on this channel.
+    ///
+    /// If the authentication cookie is omitted, a random one is sent, which
+    /// is rejected by the local X server. Pass the cookie of the local
+    /// display (e.g. from `xauth list`) to forward the connections as is.
+    pub async fn request_x11<'a>(
+        &'a mut self,
+        single_connection: bool,
+        auth_proto: Option<&'a str>,
+        auth_cookie: Option<&'a str>,
+        screen: i32,
+    ) -> Result<()> {
+        let span = op_span!(
+            self.inner.sess.borrow(),
+            "request_x11",
+            channel_id = self.inner.id,
+            single_connection,
+            screen
+        );
+        let auth_proto = auth_proto.map(CString::new).transpose()?;
+        let auth_cookie = auth_cookie.map(CString::new).transpose()?;
+        let timer = BlockTimer::default();
+        instrument(
+            span,
+            poll_fn(|cx| {
+                let channel = self.inner.raw;
+                self.inner.poll_with(cx, &timer, |sess| {
+                    sess.rc(unsafe {
+                        ffi::libssh2_channel_x11_req_ex(
+                            channel.as_ptr(),
+                            single_connection as libc::c_int,
+                            auth_proto.as_ref().map_or(ptr::null(), |s| s.as_ptr()),
+                            auth_cookie.as_ref().map_or(ptr::null(), |s| s.as_ptr()),
+                            screen as libc::c_int,
+                        )
+                    })
+                    .map(drop)
+                })
+            }),
+        )
+        .await
+    }
+
+    /// Request the agent forwarding on this channel.
+    ///
+    /// The connections to the forwarded agent are opened by the remote host
+    /// as the channels yielded from `auth_agent_channels`, which can be
+    /// bridged to the local agent with `AuthAgentChannels::forward_to_agent`.
+    pub async fn request_auth_agent(&mut self) -> Result<()> {
+        let span = op_span!(
+            self.inner.sess.borrow(),
+            "request_auth_agent",
+            channel_id = self.inner.id
+        );
+        let timer = BlockTimer::default();
+        instrument(
+            span,
+            poll_fn(|cx| {
+                let channel = self.inner.raw;
+                self.inner.poll_with(cx, &timer, |sess| {
+                    sess.rc(unsafe { sys::libssh2_channel_request_auth_agent(channel.as_ptr()) })
+                        .map(drop)
+                })
+            }),
+        )
+        .await
+    }
+
+    /// Return the stream of the X11 channels opened by the remote host.
+    ///
+    /// The X11 channels are shared by the session, so they are yielded
+    /// regardless of the channel that requested the X11 forwarding. The
+    /// stream ends when the remote host sends EOF on this channel.
+    pub fn x11_channels(&self) -> X11Channels {
+        X11Channels {
+            inner: self.inner.clone(),
+            timer: BlockTimer::default(),
+        }
+    }
+
+    /// Return the stream of the agent channels opened by the remote host.
+    ///
+    /// As with `x11_channels`, the agent channels are shared by the session,
+    /// and the stream ends when the remote host sends EOF on this channel.
+    pub fn auth_agent_channels(&self) -> AuthAgentChannels {
+        AuthAgentChannels {
+            inner: self.inner.clone(),
+            timer: BlockTimer::default(),
+        }
+    }
+
+    /// Notify the remote host that the size of the pseudo-terminal has changed.
+    pub async fn resize(&mut self, cols: u32, rows: u32, px_w: u32, px_h: u32) -> Result<()> {
+        let span = op_span!(
+            self.inner.sess.borrow(),
+            "resize",
+            channel_id = self.inner.id,
+            cols,
+            rows
+        );
+        let timer = BlockTimer::default();
+        instrument(
+            span,
+            poll_fn(|cx| {
+                let channel = self.inner.raw;
+                self.inner.poll_with(cx, &timer, |sess| {
+                    sess.rc(unsafe {
+                        sys::libssh2_channel_request_pty_size_ex(
+                            channel.as_ptr(),
+                            cols as libc::c_int,
+                            rows as libc::c_int,
+                            px_w as libc::c_int,
+                            px_h as libc::c_int,
+                        )
+                    })
+                    .map(drop)
+                })
+            }),
+        )
+        .await
+    }
+
+    /// Change how the extended data, i.e. the standard error, is handled.
+    ///
+    /// In the ignore mode, the extended data is discarded, and in the merge
+    /// mode, it is interleaved into the stream 0 as with `2>&1`. Either way,
+    /// the remote process will not be blocked on the unread standard error.
+    pub async fn handle_extended_data(&mut self, mode: ExtendedData) -> Result<()> {
+        let span = op_span!(
+            self.inner.sess.borrow(),
+            "handle_extended_data",
+            channel_id = self.inner.id,
+            mode = ?mode
+        );
+        let timer = BlockTimer::default();
+        instrument(
+            span,
+            poll_fn(|cx| {
+                let channel = self.inner.raw;
+                self.inner.poll_with(cx, &timer, |sess| {
+                    sess.rc(unsafe {
+                        sys::libssh2_channel_handle_extended_data2(channel.as_ptr(), mode.as_raw())
+                    })
+                    .map(drop)
+                })
+            }),
+        )
+        .await
+    }
+
     /// Return a handle to a particular stream for this channel.
-    pub fn stream<'a>(&'a mut self, stream_id: i32) -> Stream<'a, 'sess> {
+    pub fn stream(&mut self, stream_id: i32) -> Stream<'_> {
         Stream {
             channel: self,
             stream_id,
         }
     }
 
-    pub fn exit_status(&self) -> Result<i32> {
-        unsafe { Ok(sys::libssh2_channel_get_exit_status(self.raw.as_ptr())) }
+    /// Split the channel into the separately owned handles of the standard
+    /// input, output and error streams.
+    ///
+    /// The handles can be polled concurrently from different tasks, e.g. to
+    /// drain the standard error while writing to the standard input. The
+    /// channel is still usable for the other operations such as `wait`.
+    pub fn split(&self) -> (ChannelStdin, ChannelStdout, ChannelStderr) {
+        (
+            ChannelStdin {
+                inner: self.inner.clone(),
+                timer: BlockTimer::default(),
+            },
+            ChannelStdout {
+                inner: self.inner.clone(),
+                timer: BlockTimer::default(),
+            },
+            ChannelStderr {
+                inner: self.inner.clone(),
+                timer: BlockTimer::default(),
+            },
+        )
+    }
+
+    /// Return the stream of the lines of the standard output and error.
+    pub fn lines(&self) -> Lines {
+        Lines::new(self)
+    }
+
+    /// Return a snapshot of the traffic statistics of this channel.
+    pub fn stats(&self) -> Stats {
+        self.inner.stats.get()
+    }
+
+    /// Return the state of the receive window of this channel.
+    pub fn read_window(&self) -> ReadWindow {
+        let mut available = 0;
+        let mut initial = 0;
+        let remaining = unsafe {
+            sys::libssh2_channel_window_read_ex(
+                self.inner.raw.as_ptr(),
+                &mut available,
+                &mut initial,
+            )
+        };
+        ReadWindow {
+            remaining: remaining as u32,
+            available: available as u32,
+            initial: initial as u32,
+        }
+    }
+
+    /// Return the state of the send window of this channel.
+    pub fn write_window(&self) -> WriteWindow {
+        let mut initial = 0;
+        let remaining =
+            unsafe { sys::libssh2_channel_window_write_ex(self.inner.raw.as_ptr(), &mut initial) };
+        WriteWindow {
+            remaining: remaining as u32,
+            initial: initial as u32,
+        }
+    }
+
+    /// Adjust the receive window of this channel, and return the new window size.
+    ///
+    /// Unless `force` is set, an adjustment smaller than the packet size is
+    /// queued locally until the accumulated amount is worth sending.
+    pub async fn adjust_receive_window(&mut self, adjustment: u32, force: bool) -> Result<u32> {
+        let span = op_span!(
+            self.inner.sess.borrow(),
+            "adjust_receive_window",
+            channel_id = self.inner.id,
+            adjustment,
+            force
+        );
+        let timer = BlockTimer::default();
+        instrument(
+            span,
+            poll_fn(|cx| {
+                let channel = self.inner.raw;
+                self.inner.poll_with(cx, &timer, |sess| {
+                    let mut window = 0;
+                    sess.rc(unsafe {
+                        sys::libssh2_channel_receive_window_adjust2(
+                            channel.as_ptr(),
+                            adjustment as libc::c_ulong,
+                            force as libc::c_uchar,
+                            &mut window,
+                        )
+                    })?;
+                    Ok(window)
+                })
+            }),
+        )
+        .await
+    }
+
+    /// Return the exit status of the remote process.
+    ///
+    /// This method returns `None` until the remote host closes the channel,
+    /// since the exit status is not reliable before that.
+    pub fn exit_status(&self) -> Result<Option<ExitStatus>> {
+        if self.inner.closed.get() {
+            self.inner.read_exit_status().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Wait for the remote host to close the channel, and return the exit status.
+    ///
+    /// The remaining output of the remote process must be consumed before
+    /// calling this method, otherwise the remote host may be blocked on the
+    /// full channel window and never close the channel.
+    pub async fn wait(&mut self) -> Result<ExitStatus> {
+        let span = op_span!(self.inner.sess.borrow(), "wait", channel_id = self.inner.id);
+        let timer = BlockTimer::default();
+        instrument(span, async {
+            poll_fn(|cx| self.inner.poll_wait_eof(cx, &timer)).await?;
+            poll_fn(|cx| self.inner.poll_wait_closed(cx, &timer)).await
+        })
+        .await?;
+        self.inner.read_exit_status()
+    }
+
+    /// Copy the local streams to and from the standard streams of the remote
+    /// process, and wait for it to exit.
+    ///
+    /// EOF is sent to the remote process when `stdin` ends. The outputs are
+    /// drained until the remote host sends EOF, at which point the rest of
+    /// `stdin` is left unread, since the remote process no longer consumes
+    /// it.
+    pub async fn pipe<I, O, E>(&mut self, stdin: I, stdout: O, stderr: E) -> Result<PipeStatus>
+    where
+        I: AsyncRead + Unpin,
+        O: AsyncWrite + Unpin,
+        E: AsyncWrite + Unpin,
+    {
+        let (mut remote_stdin, mut remote_stdout, mut remote_stderr) = self.split();
+        let (stdin_bytes, stdout_bytes, stderr_bytes) = copy_streams(
+            Some((stdin, &mut remote_stdin)),
+            Some((&mut remote_stdout, stdout)),
+            Some((&mut remote_stderr, stderr)),
+        )
+        .await?;
+
+        let status = self.wait().await?;
+        Ok(PipeStatus {
+            status,
+            stdin_bytes,
+            stdout_bytes,
+            stderr_bytes,
+        })
+    }
+
+    /// Return whether the remote host has sent EOF on this channel.
+    pub fn eof(&self) -> bool {
+        self.inner.eof()
+    }
+
+    /// Send EOF to the remote host, indicating that no more data will be sent.
+    ///
+    /// The channel remains open, so the output of the remote process can
+    /// still be read after calling this method.
+    pub async fn send_eof(&mut self) -> Result<()> {
+        let span = op_span!(
+            self.inner.sess.borrow(),
+            "send_eof",
+            channel_id = self.inner.id
+        );
+        let timer = BlockTimer::default();
+        instrument(span, poll_fn(|cx| self.inner.poll_send_eof(cx, &timer))).await
+    }
+
+    /// Wait for the remote host to send EOF.
+    pub async fn wait_eof(&mut self) -> Result<()> {
+        let span = op_span!(
+            self.inner.sess.borrow(),
+            "wait_eof",
+            channel_id = self.inner.id
+        );
+        let timer = BlockTimer::default();
+        instrument(span, poll_fn(|cx| self.inner.poll_wait_eof(cx, &timer))).await
+    }
+
+    /// Wait for the remote host to close the channel.
+    ///
+    /// The remote host must have sent EOF before calling this method.
+    pub async fn wait_closed(&mut self) -> Result<()> {
+        let span = op_span!(
+            self.inner.sess.borrow(),
+            "wait_closed",
+            channel_id = self.inner.id
+        );
+        let timer = BlockTimer::default();
+        instrument(span, poll_fn(|cx| self.inner.poll_wait_closed(cx, &timer))).await
+    }
+
+    /// Close the channel in both directions.
+    pub async fn close(&mut self) -> Result<()> {
+        let span = op_span!(
+            self.inner.sess.borrow(),
+            "close",
+            channel_id = self.inner.id
+        );
+        let timer = BlockTimer::default();
+        instrument(span, poll_fn(|cx| self.inner.poll_close(cx, &timer))).await
+    }
+}
+
+impl ChannelInner {
+    /// Call the libssh2 function on the session, and account the time the
+    /// operation has waited for the socket to this channel.
+    fn poll_with<F, R>(
+        &self,
+        cx: &mut task::Context<'_>,
+        timer: &BlockTimer,
+        f: F,
+    ) -> Poll<Result<R>>
+    where
+        F: FnOnce(&mut SessionInner) -> std::result::Result<R, Ssh2Error>,
+    {
+        let res = self.sess.borrow_mut().poll_with(cx, timer, f);
+        let (read, write) = timer.take_elapsed();
+        let mut stats = self.stats.get();
+        stats.record_blocked(read, write);
+        self.stats.set(stats);
+        res
+    }
+
+    fn read_exit_status(&self) -> Result<ExitStatus> {
+        let code = unsafe { sys::libssh2_channel_get_exit_status(self.raw.as_ptr()) };
+
+        let mut signal = ptr::null_mut();
+        let mut signal_len = 0;
+        let mut errmsg = ptr::null_mut();
+        let mut errmsg_len = 0;
+        let mut langtag = ptr::null_mut();
+        let mut langtag_len = 0;
+        let rc = unsafe {
+            sys::libssh2_channel_get_exit_signal(
+                self.raw.as_ptr(),
+                &mut signal,
+                &mut signal_len,
+                &mut errmsg,
+                &mut errmsg_len,
+                &mut langtag,
+                &mut langtag_len,
+            )
+        };
+        let sess = self.sess.borrow().as_ptr();
+        if rc != 0 {
+            return Err(unsafe { Ssh2Error::last_error(sess) }
+                .unwrap_or_else(|| Ssh2Error::from_code(rc))
+                .into());
+        }
+
+        let take = |ptr: *mut libc::c_char, len: libc::size_t| {
+            if ptr.is_null() {
+                return None;
+            }
+            let s = unsafe { std::slice::from_raw_parts(ptr as *const u8, len) };
+            let s = String::from_utf8_lossy(s).into_owned();
+            unsafe { sys::libssh2_free(sess, ptr as *mut libc::c_void) };
+            Some(s)
+        };
+
+        Ok(ExitStatus {
+            code,
+            signal: take(signal, signal_len),
+            error_message: take(errmsg, errmsg_len),
+            language_tag: take(langtag, langtag_len),
+        })
     }
 
     fn poll_read(
-        &mut self,
+        &self,
         cx: &mut task::Context<'_>,
+        timer: &BlockTimer,
         stream_id: i32,
         dst: &mut [u8],
     ) -> Poll<Result<usize>> {
-        let channel = &mut self.raw;
-        self.sess.poll_with(cx, |sess| {
+        let _enter = tracing::trace_span!("read", channel_id = self.id, stream_id).entered();
+        let channel = self.raw;
+        let res = self.poll_with(cx, timer, |sess| {
             sess.rc(unsafe {
                 sys::libssh2_channel_read_ex(
-                    channel.as_mut(),
+                    channel.as_ptr(),
                     stream_id as libc::c_int,
                     dst.as_mut_ptr() as *mut libc::c_char,
                     dst.len() as libc::size_t,
                 )
             })
             .map(|n| n as usize)
-        })
+        });
+        if let Poll::Ready(Ok(n)) = res {
+            let mut stats = self.stats.get();
+            stats.record_received(n);
+            self.stats.set(stats);
+        }
+        res
     }
 
     fn poll_write(
-        &mut self,
+        &self,
         cx: &mut task::Context<'_>,
+        timer: &BlockTimer,
         stream_id: i32,
         src: &[u8],
     ) -> Poll<Result<usize>> {
-        let channel = &mut self.raw;
-        self.sess.poll_with(cx, |sess| {
+        let _enter = tracing::trace_span!("write", channel_id = self.id, stream_id).entered();
+        let channel = self.raw;
+        let res = self.poll_with(cx, timer, |sess| {
             sess.rc(unsafe {
                 sys::libssh2_channel_write_ex(
-                    channel.as_mut(),
+                    channel.as_ptr(),
                     stream_id,
                     src.as_ptr() as *const libc::c_char,
                     src.len(),
                 )
             })
             .map(|n| n as usize)
+        });
+        if let Poll::Ready(Ok(n)) = res {
+            let mut stats = self.stats.get();
+            stats.record_sent(n);
+            self.stats.set(stats);
+        }
+        res
+    }
+
+    fn poll_flush(
+        &self,
+        cx: &mut task::Context<'_>,
+        timer: &BlockTimer,
+        stream_id: i32,
+    ) -> Poll<Result<()>> {
+        let _enter = tracing::trace_span!("flush", channel_id = self.id, stream_id).entered();
+        let channel = self.raw;
+        self.poll_with(cx, timer, |sess| {
+            sess.rc(unsafe { sys::libssh2_channel_flush_ex(channel.as_ptr(), stream_id) })
+                .map(drop)
         })
     }
 
-    fn poll_flush(&mut self, cx: &mut task::Context<'_>, stream_id: i32) -> Poll<Result<()>> {
-        let channel = &mut self.raw;
-        self.sess.poll_with(cx, |sess| {
-            sess.rc(unsafe { sys::libssh2_channel_flush_ex(channel.as_mut(), stream_id) })
+    fn eof(&self) -> bool {
+        unsafe { sys::libssh2_channel_eof(self.raw.as_ptr()) > 0 }
+    }
+
+    fn poll_send_eof(&self, cx: &mut task::Context<'_>, timer: &BlockTimer) -> Poll<Result<()>> {
+        let channel = self.raw;
+        self.poll_with(cx, timer, |sess| {
+            sess.rc(unsafe { sys::libssh2_channel_send_eof(channel.as_ptr()) })
                 .map(drop)
         })
     }
 
-    fn poll_close(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<()>> {
-        let channel = &mut self.raw;
-        self.sess.poll_with(cx, |sess| {
-            sess.rc(unsafe { sys::libssh2_channel_close(channel.as_mut()) })
+    fn poll_wait_eof(&self, cx: &mut task::Context<'_>, timer: &BlockTimer) -> Poll<Result<()>> {
+        let channel = self.raw;
+        self.poll_with(cx, timer, |sess| {
+            sess.rc(unsafe { sys::libssh2_channel_wait_eof(channel.as_ptr()) })
                 .map(drop)
         })
     }
 
-    pub async fn close(&mut self) -> Result<()> {
-        poll_fn(|cx| self.poll_close(cx)).await
+    fn poll_wait_closed(&self, cx: &mut task::Context<'_>, timer: &BlockTimer) -> Poll<Result<()>> {
+        let channel = self.raw;
+        let res = self.poll_with(cx, timer, |sess| {
+            sess.rc(unsafe { sys::libssh2_channel_wait_closed(channel.as_ptr()) })
+                .map(drop)
+        });
+        if let Poll::Ready(Ok(())) = res {
+            self.closed.set(true);
+        }
+        res
+    }
+
+    fn poll_close(&self, cx: &mut task::Context<'_>, timer: &BlockTimer) -> Poll<Result<()>> {
+        let channel = self.raw;
+        let res = self.poll_with(cx, timer, |sess| {
+            sess.rc(unsafe { sys::libssh2_channel_close(channel.as_ptr()) })
+                .map(drop)
+        });
+        // libssh2 waits for the remote host to close the channel as well.
+        if let Poll::Ready(Ok(())) = res {
+            self.closed.set(true);
+        }
+        res
+    }
+
+    /// Poll a channel opened by the remote host and queued in the session,
+    /// until the remote host sends EOF on this channel.
+    fn poll_accept(
+        &self,
+        cx: &mut task::Context<'_>,
+        timer: &BlockTimer,
+        take: fn(&SessionInner) -> Option<NonNull<sys::LIBSSH2_CHANNEL>>,
+    ) -> Poll<Option<Result<Channel>>> {
+        // Drive the transport with an empty read, during which libssh2
+        // accepts the channels.
+        let channel = self.raw;
+        let res = self.poll_with(cx, timer, |sess| {
+            sess.rc(unsafe {
+                sys::libssh2_channel_read_ex(channel.as_ptr(), 0, ptr::null_mut(), 0)
+            })
+        });
+
+        let mut sess = self.sess.borrow_mut();
+        if let Some(raw) = take(&sess) {
+            let id = sess.next_channel_id();
+            drop(sess);
+            return Poll::Ready(Some(Ok(Channel::new(raw, id, self.sess.clone()))));
+        }
+
+        match res {
+            Poll::Ready(Ok(..)) if self.eof() => Poll::Ready(None),
+            Poll::Ready(Ok(..)) => {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+            Poll::Ready(Err(err)) => Poll::Ready(Some(Err(err))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// The state of the receive window of a channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadWindow {
+    remaining: u32,
+    available: u32,
+    initial: u32,
+}
+
+impl ReadWindow {
+    /// Return the number of bytes the remote host is allowed to send.
+    pub fn remaining(&self) -> u32 {
+        self.remaining
+    }
+
+    /// Return the number of bytes received but not read yet.
+    pub fn available(&self) -> u32 {
+        self.available
+    }
+
+    /// Return the window size requested when the channel was opened.
+    pub fn initial(&self) -> u32 {
+        self.initial
+    }
+}
+
+/// The state of the send window of a channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WriteWindow {
+    remaining: u32,
+    initial: u32,
+}
+
+impl WriteWindow {
+    /// Return the number of bytes that can be sent without blocking.
+    pub fn remaining(&self) -> u32 {
+        self.remaining
+    }
+
+    /// Return the window size granted by the remote host when the channel was opened.
+    pub fn initial(&self) -> u32 {
+        self.initial
+    }
+}
+
+fn signal_name(name: &str) -> &str {
+    name.strip_prefix("SIG").unwrap_or(name)
+}
+
+/// The handling modes of the extended data on a channel.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ExtendedData {
+    /// Queue the extended data to be read from the separate stream.
+    Normal,
+    /// Discard the extended data.
+    Ignore,
+    /// Merge the extended data into the stream 0.
+    Merge,
+}
+
+impl ExtendedData {
+    fn as_raw(self) -> libc::c_int {
+        match self {
+            Self::Normal => sys::LIBSSH2_CHANNEL_EXTENDED_DATA_NORMAL,
+            Self::Ignore => sys::LIBSSH2_CHANNEL_EXTENDED_DATA_IGNORE,
+            Self::Merge => sys::LIBSSH2_CHANNEL_EXTENDED_DATA_MERGE,
+        }
+    }
+}
+
+/// Copy the local input to the standard input of the remote process, and
+/// its standard output and error to the local outputs.
+///
+/// EOF is sent to the remote process when the input ends. The outputs are
+/// flushed as each chunk arrives, and drained until the remote host sends
+/// EOF, at which point the rest of the input is left unread. Returns the number of bytes copied from the input
+/// and to the outputs respectively.
+pub(crate) async fn copy_streams<I, O, E>(
+    input: Option<(I, &mut ChannelStdin)>,
+    stdout: Option<(&mut ChannelStdout, O)>,
+    stderr: Option<(&mut ChannelStderr, E)>,
+) -> io::Result<(u64, u64, u64)>
+where
+    I: AsyncRead + Unpin,
+    O: AsyncWrite + Unpin,
+    E: AsyncWrite + Unpin,
+{
+    async fn copy_output<R, W>(output: Option<(&mut R, W)>) -> io::Result<u64>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let (reader, mut writer) = match output {
+            Some(output) => output,
+            None => return Ok(0),
+        };
+        let mut buf = vec![0u8; 32 * 1024];
+        let mut total = 0;
+        loop {
+            let n = reader.read(&mut buf).await?;
+            if n == 0 {
+                return Ok(total);
+            }
+            writer.write_all(&buf[..n]).await?;
+            writer.flush().await?;
+            total += n as u64;
+        }
+    }
+    let outputs = futures::future::try_join(copy_output(stdout), copy_output(stderr));
+
+    let mut input_bytes = 0;
+    let (stdout_bytes, stderr_bytes) = match input {
+        Some((mut reader, writer)) => {
+            let input = async {
+                let mut buf = vec![0u8; 32 * 1024];
+                loop {
+                    let n = reader.read(&mut buf).await?;
+                    if n == 0 {
+                        break;
+                    }
+                    writer.write_all(&buf[..n]).await?;
+                    input_bytes += n as u64;
+                }
+                writer.shutdown().await
+            };
+            futures::pin_mut!(input, outputs);
+            tokio::select! {
+                res = &mut outputs => res?,
+                res = &mut input => {
+                    res?;
+                    outputs.await?
+                }
+            }
+        }
+        None => outputs.await?,
+    };
+    Ok((input_bytes, stdout_bytes, stderr_bytes))
+}
+
+/// The result of `Channel::pipe`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PipeStatus {
+    /// The exit status of the remote process.
+    pub status: ExitStatus,
+    /// The number of bytes sent to the standard input.
+    pub stdin_bytes: u64,
+    /// The number of bytes received from the standard output.
+    pub stdout_bytes: u64,
+    /// The number of bytes received from the standard error.
+    pub stderr_bytes: u64,
+}
+
+/// The exit status of a remote process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExitStatus {
+    code: libc::c_int,
+    signal: Option<String>,
+    error_message: Option<String>,
+    language_tag: Option<String>,
+}
+
+impl ExitStatus {
+    /// Return whether the remote process has exited successfully.
+    pub fn success(&self) -> bool {
+        self.code() == Some(0)
+    }
+
+    /// Return the exit code of the remote process, if it has exited normally.
+    pub fn code(&self) -> Option<i32> {
+        match self.signal {
+            Some(..) => None,
+            None => Some(self.code),
+        }
+    }
+
+    /// Return the name of the signal, without the leading `SIG`, that
+    /// terminated the remote process.
+    pub fn signal(&self) -> Option<&str> {
+        self.signal.as_deref()
+    }
+
+    /// Return the error message sent along with the exit signal, if any.
+    pub fn error_message(&self) -> Option<&str> {
+        self.error_message.as_deref()
+    }
+
+    /// Return the language tag of the error message, if any.
+    pub fn language_tag(&self) -> Option<&str> {
+        self.language_tag.as_deref()
+    }
+}
+
+impl fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.signal {
+            Some(ref signal) => write!(f, "signal: {}", signal),
+            None => write!(f, "exit code: {}", self.code),
+        }
     }
 }
 
-impl AsyncRead for Channel<'_> {
+impl AsyncRead for Channel {
     fn poll_read(
         self: Pin<&mut Self>,
         cx: &mut task::Context<'_>,
@@ -177,7 +1048,7 @@ impl AsyncRead for Channel<'_> {
     }
 }
 
-impl AsyncWrite for Channel<'_> {
+impl AsyncWrite for Channel {
     fn poll_write(
         self: Pin<&mut Self>,
         cx: &mut task::Context<'_>,
@@ -190,52 +1061,159 @@ impl AsyncWrite for Channel<'_> {
         Pin::new(&mut self.get_mut().stream(0)).poll_flush(cx)
     }
 
+    /// Send EOF to the remote host, as with the half-close of TCP.
     fn poll_shutdown(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
-        self.get_mut()
-            .poll_close(cx)
+        self.inner
+            .poll_send_eof(cx, &self.write_timer)
             .map_err(|err| err.into_io_error())
     }
 }
 
 /// The stream associated with a `Channel`.
-pub struct Stream<'a, 'sess> {
-    channel: &'a mut Channel<'sess>,
+pub struct Stream<'a> {
+    channel: &'a mut Channel,
     stream_id: i32,
 }
 
-impl AsyncRead for Stream<'_, '_> {
+impl AsyncRead for Stream<'_> {
     fn poll_read(
         self: Pin<&mut Self>,
         cx: &mut task::Context<'_>,
         dst: &mut [u8],
     ) -> Poll<io::Result<usize>> {
-        let me = self.get_mut();
-        me.channel
-            .poll_read(cx, me.stream_id, dst)
+        self.channel
+            .inner
+            .poll_read(cx, &self.channel.read_timer, self.stream_id, dst)
             .map_err(|err| err.into_io_error())
     }
 }
 
-impl AsyncWrite for Stream<'_, '_> {
+impl AsyncWrite for Stream<'_> {
     fn poll_write(
         self: Pin<&mut Self>,
         cx: &mut task::Context<'_>,
         src: &[u8],
     ) -> Poll<io::Result<usize>> {
-        let me = self.get_mut();
-        me.channel
-            .poll_write(cx, me.stream_id, src)
+        self.channel
+            .inner
+            .poll_write(cx, &self.channel.write_timer, self.stream_id, src)
             .map_err(|err| err.into_io_error())
     }
 
     fn poll_flush(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
-        let me = self.get_mut();
-        me.channel
-            .poll_flush(cx, me.stream_id)
+        self.channel
+            .inner
+            .poll_flush(cx, &self.channel.write_timer, self.stream_id)
+            .map_err(|err| err.into_io_error())
+    }
+
+    /// Send EOF to the remote host, as with the half-close of TCP.
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        self.channel
+            .inner
+            .poll_send_eof(cx, &self.channel.write_timer)
             .map_err(|err| err.into_io_error())
     }
+}
+
+/// The standard input of a remote process, obtained from `Channel::split`.
+pub struct ChannelStdin {
+    inner: Rc<ChannelInner>,
+    timer: BlockTimer,
+}
+
+impl AsyncWrite for ChannelStdin {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        src: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.inner
+            .poll_write(cx, &self.timer, 0, src)
+            .map_err(|err| err.into_io_error())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        self.inner
+            .poll_flush(cx, &self.timer, 0)
+            .map_err(|err| err.into_io_error())
+    }
+
+    /// Send EOF to the remote host, as with the half-close of TCP.
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        self.inner
+            .poll_send_eof(cx, &self.timer)
+            .map_err(|err| err.into_io_error())
+    }
+}
+
+/// The standard output of a remote process, obtained from `Channel::split`.
+pub struct ChannelStdout {
+    inner: Rc<ChannelInner>,
+    timer: BlockTimer,
+}
+
+impl AsyncRead for ChannelStdout {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        dst: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.inner
+            .poll_read(cx, &self.timer, 0, dst)
+            .map_err(|err| err.into_io_error())
+    }
+}
+
+/// The standard error of a remote process, obtained from `Channel::split`.
+pub struct ChannelStderr {
+    inner: Rc<ChannelInner>,
+    timer: BlockTimer,
+}
+
+impl AsyncRead for ChannelStderr {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        dst: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.inner
+            .poll_read(cx, &self.timer, SSH_EXTENDED_DATA_STDERR, dst)
+            .map_err(|err| err.into_io_error())
+    }
+}
+
+/// The stream of the X11 channels opened by the remote host, obtained from
+/// `Channel::x11_channels`.
+pub struct X11Channels {
+    inner: Rc<ChannelInner>,
+    timer: BlockTimer,
+}
+
+impl futures::Stream for X11Channels {
+    type Item = Result<Channel>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<Option<Self::Item>> {
+        let _enter = tracing::trace_span!("x11_accept", channel_id = self.inner.id).entered();
+        self.inner
+            .poll_accept(cx, &self.timer, SessionInner::take_x11_channel)
+    }
+}
+
+/// The stream of the agent channels opened by the remote host, obtained from
+/// `Channel::auth_agent_channels`.
+pub struct AuthAgentChannels {
+    inner: Rc<ChannelInner>,
+    timer: BlockTimer,
+}
+
+impl futures::Stream for AuthAgentChannels {
+    type Item = Result<Channel>;
 
-    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
-        Poll::Ready(Ok(()))
+    fn poll_next(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<Option<Self::Item>> {
+        let _enter =
+            tracing::trace_span!("auth_agent_accept", channel_id = self.inner.id).entered();
+        self.inner
+            .poll_accept(cx, &self.timer, SessionInner::take_auth_agent_channel)
     }
 }
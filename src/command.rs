@@ -0,0 +1,756 @@
+//! Execution of remote commands, in the manner of `tokio::process`.
+
+use crate::{
+    channel::{
+        copy_streams, Channel, ChannelStderr, ChannelStdin, ChannelStdout, ExitStatus, ExtendedData,
+    },
+    error::Result,
+    pty::{PtySize, TerminalModes},
+    session::Authenticated,
+    util::random_marker,
+    Session,
+};
+use std::{
+    fmt::{self, Write as _},
+    time::Duration,
+};
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWriteExt};
+
+#[cfg(unix)]
+use crate::terminal::stdin;
+#[cfg(not(unix))]
+use tokio::io::stdin;
+
+/// The configuration of a standard stream of the remote process.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Stdio(StdioKind);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum StdioKind {
+    Piped,
+    Null,
+    Inherit,
+}
+
+impl Stdio {
+    /// Connect the stream to the handle of `RemoteChild`.
+    pub fn piped() -> Self {
+        Self(StdioKind::Piped)
+    }
+
+    /// Discard the output, or close the input immediately.
+    pub fn null() -> Self {
+        Self(StdioKind::Null)
+    }
+
+    /// Connect the stream to the corresponding stream of the current process.
+    pub fn inherit() -> Self {
+        Self(StdioKind::Inherit)
+    }
+}
+
+/// A builder of the command executed on the remote host.
+///
+/// The command line is composed from the program and the arguments with
+/// the POSIX shell quoting, and is executed by the login shell of the
+/// remote user.
+pub struct RemoteCommand<'a> {
+    session: &'a Session<Authenticated>,
+    program: String,
+    args: Vec<String>,
+    envs: Vec<(String, String)>,
+    current_dir: Option<String>,
+    pty: Option<(String, TerminalModes, PtySize)>,
+    stdin: Option<Stdio>,
+    stdout: Option<Stdio>,
+    stderr: Option<Stdio>,
+    kill_on_drop: bool,
+    escalation: Option<Escalation>,
+}
+
+impl<'a> RemoteCommand<'a> {
+    pub(crate) fn new(session: &'a Session<Authenticated>, program: &str) -> Self {
+        Self {
+            session,
+            program: program.to_owned(),
+            args: vec![],
+            envs: vec![],
+            current_dir: None,
+            pty: None,
+            stdin: None,
+            stdout: None,
+            stderr: None,
+            kill_on_drop: false,
+            escalation: None,
+        }
+    }
+
+    /// Add an argument to pass to the program.
+    pub fn arg(&mut self, arg: impl AsRef<str>) -> &mut Self {
+        self.args.push(arg.as_ref().to_owned());
+        self
+    }
+
+    /// Add multiple arguments to pass to the program.
+    pub fn args<I>(&mut self, args: I) -> &mut Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        for arg in args {
+            self.arg(arg);
+        }
+        self
+    }
+
+    /// Set an environment variable of the remote process.
+    ///
+    /// The variables are sent with the `env` requests, which the remote host
+    /// may refuse unless permitted by `AcceptEnv`.
+    pub fn env(&mut self, key: impl AsRef<str>, value: impl AsRef<str>) -> &mut Self {
+        self.envs
+            .push((key.as_ref().to_owned(), value.as_ref().to_owned()));
+        self
+    }
+
+    /// Set multiple environment variables of the remote process.
+    pub fn envs<I, K, V>(&mut self, vars: I) -> &mut Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        for (key, value) in vars {
+            self.env(key, value);
+        }
+        self
+    }
+
+    /// Set the working directory of the remote process.
+    pub fn current_dir(&mut self, dir: impl AsRef<str>) -> &mut Self {
+        self.current_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Allocate a pseudo-terminal for the remote process.
+    ///
+    /// Note that the standard error is merged into the standard output on a
+    /// pseudo-terminal.
+    pub fn pty(&mut self, term: &str, modes: Option<TerminalModes>, size: PtySize) -> &mut Self {
+        self.pty = Some((term.to_owned(), modes.unwrap_or_default(), size));
+        self
+    }
+
+    /// Configure the standard input of the remote process.
+    pub fn stdin(&mut self, cfg: Stdio) -> &mut Self {
+        self.stdin = Some(cfg);
+        self
+    }
+
+    /// Configure the standard output of the remote process.
+    pub fn stdout(&mut self, cfg: Stdio) -> &mut Self {
+        self.stdout = Some(cfg);
+        self
+    }
+
+    /// Configure the standard error of the remote process.
+    pub fn stderr(&mut self, cfg: Stdio) -> &mut Self {
+        self.stderr = Some(cfg);
+        self
+    }
+
+    /// Send `KILL` to the remote process when the `RemoteChild` is dropped
+    /// before the remote process finishes.
+    pub fn kill_on_drop(&mut self, kill_on_drop: bool) -> &mut Self {
+        self.kill_on_drop = kill_on_drop;
+        self
+    }
+
+    /// Run the command as another user with `sudo`, `su` or `doas`.
+    ///
+    /// The password is sent only after the password prompt is detected, and
+    /// never appears in the command line. If the password is rejected or
+    /// missing, `spawn` fails with an error whose source is an I/O error of
+    /// the kind `PermissionDenied`, whereas the failure of the command
+    /// itself is reported by the exit status as usual.
+    ///
+    /// The environment variables set by `env` may be reset by the
+    /// escalation command. `su` and `doas` are run with `LC_ALL=C` so that
+    /// their prompts are in English, and `LC_ALL` is unset for the command.
+    pub fn escalate(&mut self, escalation: Escalation) -> &mut Self {
+        self.escalation = Some(escalation);
+        self
+    }
+
+    /// Return the command line executed on the remote host, excluding the
+    /// privilege escalation.
+    pub fn command_line(&self) -> String {
+        let mut line = String::new();
+        if let Some(ref dir) = self.current_dir {
+            let _ = write!(line, "cd {} && ", quote(dir));
+        }
+        line += &quote(&self.program);
+        for arg in &self.args {
+            line.push(' ');
+            line += &quote(arg);
+        }
+        line
+    }
+
+    /// Execute the command, and return the handle to the remote process.
+    ///
+    /// The standard output and error are inherited by default, and the
+    /// standard input is null by default, so that the local standard input
+    /// is read only if requested.
+    pub async fn spawn(&mut self) -> Result<RemoteChild> {
+        self.spawn_with(Stdio::null(), Stdio::inherit(), Stdio::inherit())
+            .await
+    }
+
+    /// Execute the command, and wait for the remote process to finish.
+    ///
+    /// The standard output and error are inherited by default, and the
+    /// standard input is null by default.
+    pub async fn status(&mut self) -> Result<ExitStatus> {
+        self.spawn().await?.wait().await
+    }
+
+    /// Execute the command, and collect its output.
+    ///
+    /// The standard output and error are piped by default, and the standard
+    /// input is null by default.
+    pub async fn output(&mut self) -> Result<Output> {
+        let child = self
+            .spawn_with(Stdio::null(), Stdio::piped(), Stdio::piped())
+            .await?;
+        child.wait_with_output().await
+    }
+
+    async fn spawn_with(
+        &mut self,
+        stdin: Stdio,
+        stdout: Stdio,
+        stderr: Stdio,
+    ) -> Result<RemoteChild> {
+        let stdin = self.stdin.unwrap_or(stdin);
+        let stdout = self.stdout.unwrap_or(stdout);
+        let stderr = self.stderr.unwrap_or(stderr);
+
+        let mut channel = self.session.open_channel_session().await?;
+        for (key, value) in &self.envs {
+            channel.setenv(key, value).await?;
+        }
+        let needs_pty = self
+            .escalation
+            .as_ref()
+            .is_some_and(Escalation::requires_pty);
+        if let Some((ref term, ref modes, size)) = self.pty {
+            channel.request_pty(term, Some(modes), Some(size)).await?;
+        } else if needs_pty {
+            channel.request_pty("dumb", None, None).await?;
+        }
+        let pty = self.pty.is_some() || needs_pty;
+
+        let marker = random_marker();
+        let command_line = match self.escalation {
+            Some(ref escalation) => escalation.command_line(&self.command_line(), &marker, pty),
+            None => self.command_line(),
+        };
+        channel.exec(&command_line).await?;
+        if self.kill_on_drop {
+            channel.set_kill_on_drop(Some("KILL"));
+        }
+        if let Some(ref escalation) = self.escalation {
+            let (mut stdin_half, mut stdout_half, mut stderr_half) = channel.split();
+            if pty {
+                escalation
+                    .authenticate(&mut stdout_half, &mut stdin_half, &marker)
+                    .await?;
+            } else {
+                escalation
+                    .authenticate(&mut stderr_half, &mut stdin_half, &marker)
+                    .await?;
+            }
+        }
+
+        // The standard error is ignored after the escalation, since the
+        // password prompt of `sudo -S` is read from it.
+        if stderr == Stdio::null() {
+            channel.handle_extended_data(ExtendedData::Ignore).await?;
+        }
+        if stdin == Stdio::null() {
+            channel.send_eof().await?;
+        }
+
+        let (stdin_half, stdout_half, stderr_half) = channel.split();
+        let mut child = RemoteChild {
+            channel,
+            stdin: None,
+            stdout: None,
+            stderr: None,
+            inherit_stdin: None,
+            inherit_stdout: None,
+            inherit_stderr: None,
+            null_stdout: None,
+        };
+        match stdin.0 {
+            StdioKind::Piped => child.stdin = Some(stdin_half),
+            StdioKind::Inherit => child.inherit_stdin = Some(stdin_half),
+            StdioKind::Null => (),
+        }
+        match stdout.0 {
+            StdioKind::Piped => child.stdout = Some(stdout_half),
+            StdioKind::Inherit => child.inherit_stdout = Some(stdout_half),
+            StdioKind::Null => child.null_stdout = Some(stdout_half),
+        }
+        match stderr.0 {
+            StdioKind::Piped => child.stderr = Some(stderr_half),
+            StdioKind::Inherit => child.inherit_stderr = Some(stderr_half),
+            StdioKind::Null => (),
+        }
+        Ok(child)
+    }
+}
+
+/// The privilege escalation to run a command as another user.
+#[derive(Clone)]
+pub struct Escalation {
+    method: EscalationMethod,
+    user: Option<String>,
+    password: Option<String>,
+    timeout: Duration,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum EscalationMethod {
+    Sudo,
+    Su,
+    Doas,
+}
+
+impl fmt::Debug for Escalation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Escalation")
+            .field("method", &self.method)
+            .field("user", &self.user)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+impl Escalation {
+    /// Escalate with `sudo`.
+    ///
+    /// The password is read by `sudo -S` from the standard input, or from
+    /// the pseudo-terminal if allocated.
+    pub fn sudo() -> Self {
+        Self::new(EscalationMethod::Sudo)
+    }
+
+    /// Escalate with `su`.
+    ///
+    /// Since `su` reads the password only from a terminal, a pseudo-terminal
+    /// is allocated if not requested by `RemoteCommand::pty`.
+    pub fn su() -> Self {
+        Self::new(EscalationMethod::Su)
+    }
+
+    /// Escalate with `doas`.
+    ///
+    /// Since `doas` reads the password only from a terminal, a
+    /// pseudo-terminal is allocated if not requested by `RemoteCommand::pty`.
+    pub fn doas() -> Self {
+        Self::new(EscalationMethod::Doas)
+    }
+
+    fn new(method: EscalationMethod) -> Self {
+        Self {
+            method,
+            user: None,
+            password: None,
+            timeout: DEFAULT_ESCALATION_TIMEOUT,
+        }
+    }
+
+    /// Set the user to run the command as, `root` by default.
+    pub fn user(mut self, user: &str) -> Self {
+        self.user = Some(user.to_owned());
+        self
+    }
+
+    /// Set the password answered to the password prompt.
+    pub fn password(mut self, password: &str) -> Self {
+        self.password = Some(password.to_owned());
+        self
+    }
+
+    /// Set the time to wait for the command to start, 30 seconds by default.
+    ///
+    /// `spawn` fails with an error of the kind `TimedOut` if neither the
+    /// password prompt is answered nor the command is started in time,
+    /// e.g. when the prompt is not recognized.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn requires_pty(&self) -> bool {
+        self.method != EscalationMethod::Sudo
+    }
+
+    /// Compose the command line which prints the started marker to the
+    /// standard error just before running the command.
+    fn command_line(&self, command: &str, marker: &str, pty: bool) -> String {
+        let started = started_marker(marker);
+        let script = match self.method {
+            EscalationMethod::Sudo => format!("printf %s {} >&2; {}", started, command),
+            // The prompts of `su` and `doas` are matched in English.
+            _ => format!("printf %s {} >&2; unset LC_ALL; {}", started, command),
+        };
+        let user = self.user.as_deref().unwrap_or("root");
+        match self.method {
+            EscalationMethod::Sudo => format!(
+                "sudo {}-p {} -u {} -- sh -c {}",
+                if pty { "" } else { "-S " },
+                quote(&prompt_marker(marker)),
+                quote(user),
+                quote(&script)
+            ),
+            EscalationMethod::Su => {
+                format!("LC_ALL=C su {} -c {}", quote(user), quote(&script))
+            }
+            EscalationMethod::Doas => {
+                format!("LC_ALL=C doas -u {} sh -c {}", quote(user), quote(&script))
+            }
+        }
+    }
+
+    /// Answer the password prompt until the command is started, or the
+    /// timeout expires.
+    async fn authenticate<R>(
+        &self,
+        reader: &mut R,
+        stdin: &mut ChannelStdin,
+        marker: &str,
+    ) -> Result<()>
+    where
+        R: AsyncRead + Unpin,
+    {
+        match tokio::time::timeout(self.timeout, self.answer_prompt(reader, stdin, marker)).await {
+            Ok(res) => res,
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!(
+                    "{} did not start the command within {:?}",
+                    self.name(),
+                    self.timeout
+                ),
+            )
+            .into()),
+        }
+    }
+
+    /// Answer the password prompt until the command is started.
+    ///
+    /// The output is read byte by byte, so that the output of the command
+    /// following the started marker is left unread.
+    async fn answer_prompt<R>(
+        &self,
+        reader: &mut R,
+        stdin: &mut ChannelStdin,
+        marker: &str,
+    ) -> Result<()>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let started = started_marker(marker);
+        let prompt = prompt_marker(marker);
+        let mut output = Vec::new();
+        let mut answered = false;
+        loop {
+            let b = match reader.read_u8().await {
+                Ok(b) => b,
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Err(self.failed(&output, answered));
+                }
+                Err(err) => return Err(err.into()),
+            };
+            output.push(b);
+
+            if output.ends_with(started.as_bytes()) {
+                return Ok(());
+            }
+
+            let is_prompt = match self.method {
+                EscalationMethod::Sudo => output.ends_with(prompt.as_bytes()),
+                _ => b == b':' && is_password_prompt(&output),
+            };
+            if is_prompt {
+                if answered {
+                    return Err(permission_denied("incorrect password"));
+                }
+                let password = self
+                    .password
+                    .as_ref()
+                    .ok_or_else(|| permission_denied("password required"))?;
+                stdin
+                    .write_all(format!("{}\n", password).as_bytes())
+                    .await?;
+                stdin.flush().await?;
+                answered = true;
+                output.clear();
+            }
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self.method {
+            EscalationMethod::Sudo => "sudo",
+            EscalationMethod::Su => "su",
+            EscalationMethod::Doas => "doas",
+        }
+    }
+
+    fn failed(&self, output: &[u8], answered: bool) -> crate::Error {
+        let output = String::from_utf8_lossy(output);
+        let msg = format!("{} failed: {}", self.name(), output.trim());
+        // The failure after answering the password means that the password
+        // is rejected, since `su` and `doas` exit without asking again.
+        let kind = if answered {
+            io::ErrorKind::PermissionDenied
+        } else {
+            io::ErrorKind::Other
+        };
+        io::Error::new(kind, msg).into()
+    }
+}
+
+fn started_marker(marker: &str) -> String {
+    format!("{}_STARTED", marker)
+}
+
+fn prompt_marker(marker: &str) -> String {
+    format!("{}_PASSWORD:", marker)
+}
+
+/// Test whether the last line of the output looks like a password prompt,
+/// e.g. `Password:` or `doas (user@host) password:`.
+fn is_password_prompt(output: &[u8]) -> bool {
+    let start = output
+        .iter()
+        .rposition(|&b| b == b'\n' || b == b'\r')
+        .map_or(0, |i| i + 1);
+    String::from_utf8_lossy(&output[start..])
+        .to_lowercase()
+        .contains("password")
+}
+
+fn permission_denied(msg: &str) -> crate::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, msg).into()
+}
+
+/// A handle to the process executed on the remote host.
+pub struct RemoteChild {
+    channel: Channel,
+
+    /// The standard input of the remote process, if configured as piped.
+    pub stdin: Option<ChannelStdin>,
+
+    /// The standard output of the remote process, if configured as piped.
+    pub stdout: Option<ChannelStdout>,
+
+    /// The standard error of the remote process, if configured as piped.
+    pub stderr: Option<ChannelStderr>,
+
+    inherit_stdin: Option<ChannelStdin>,
+    inherit_stdout: Option<ChannelStdout>,
+    inherit_stderr: Option<ChannelStderr>,
+    null_stdout: Option<ChannelStdout>,
+}
+
+impl RemoteChild {
+    /// Return the channel on which the remote process is running.
+    pub fn channel(&mut self) -> &mut Channel {
+        &mut self.channel
+    }
+
+    /// Send `KILL` to the remote process, and close the channel.
+    pub async fn kill(&mut self) -> Result<()> {
+        self.channel.signal("KILL").await?;
+        self.channel.close().await
+    }
+
+    /// Wait for the remote process to finish, and return its exit status.
+    ///
+    /// The standard input is closed before waiting. The piped outputs must
+    /// be consumed concurrently, otherwise the remote process may be blocked.
+    pub async fn wait(&mut self) -> Result<ExitStatus> {
+        if let Some(mut stdin) = self.stdin.take() {
+            stdin.shutdown().await?;
+        }
+        self.forward_inherited().await?;
+        self.channel.wait().await
+    }
+
+    /// Wait for the remote process to finish, and collect the piped outputs.
+    pub async fn wait_with_output(mut self) -> Result<Output> {
+        if let Some(mut stdin) = self.stdin.take() {
+            stdin.shutdown().await?;
+        }
+
+        async fn read_to_end<R: io::AsyncRead + Unpin>(reader: Option<R>) -> io::Result<Vec<u8>> {
+            let mut buf = vec![];
+            if let Some(mut reader) = reader {
+                reader.read_to_end(&mut buf).await?;
+            }
+            Ok(buf)
+        }
+        let stdout = read_to_end(self.stdout.take());
+        let stderr = read_to_end(self.stderr.take());
+        let (stdout, stderr, ()) = futures::try_join!(stdout, stderr, async {
+            self.forward_inherited()
+                .await
+                .map_err(|err| err.into_io_error())
+        })?;
+
+        let status = self.channel.wait().await?;
+        Ok(Output {
+            status,
+            stdout,
+            stderr,
+        })
+    }
+
+    /// Copy the streams configured as inherit or null until the remote
+    /// process closes its outputs.
+    async fn forward_inherited(&mut self) -> Result<()> {
+        let Self {
+            inherit_stdin,
+            inherit_stdout,
+            inherit_stderr,
+            null_stdout,
+            ..
+        } = self;
+
+        // The null output is drained, so that the remote process is not
+        // blocked on the full channel window.
+        let stdout: Option<(_, Box<dyn io::AsyncWrite + Unpin>)> =
+            match (inherit_stdout, null_stdout) {
+                (Some(reader), _) => Some((reader, Box::new(io::stdout()))),
+                (None, Some(reader)) => Some((reader, Box::new(io::sink()))),
+                (None, None) => None,
+            };
+        copy_streams(
+            inherit_stdin.as_mut().map(|writer| (stdin(), writer)),
+            stdout,
+            inherit_stderr.as_mut().map(|reader| (reader, io::stderr())),
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+/// The output of a finished remote process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Output {
+    /// The exit status of the remote process.
+    pub status: ExitStatus,
+    /// The data written to the standard output.
+    pub stdout: Vec<u8>,
+    /// The data written to the standard error.
+    pub stderr: Vec<u8>,
+}
+
+/// The default time to wait for the escalated command to start.
+const DEFAULT_ESCALATION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Quote the word for the POSIX shell, if needed.
+///
+/// A leading `=` is quoted as well, since zsh expands `=cmd` to the path
+/// of the command.
+pub(crate) fn quote(word: &str) -> String {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "_@%+=:,./-".contains(c);
+    if !word.is_empty() && !word.starts_with('=') && word.chars().all(is_safe) {
+        return word.to_owned();
+    }
+    format!("'{}'", word.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quote_safe_word() {
+        assert_eq!(quote("ls"), "ls");
+        assert_eq!(quote("/usr/bin/env"), "/usr/bin/env");
+        assert_eq!(quote("--color=auto"), "--color=auto");
+        assert_eq!(quote("user@host:path,x+y%"), "user@host:path,x+y%");
+    }
+
+    #[test]
+    fn quote_empty() {
+        assert_eq!(quote(""), "''");
+    }
+
+    #[test]
+    fn quote_single_quote() {
+        assert_eq!(quote("'"), r#"''\'''"#);
+        assert_eq!(quote("it's"), r#"'it'\''s'"#);
+    }
+
+    #[test]
+    fn quote_special_chars() {
+        assert_eq!(quote("a b"), "'a b'");
+        assert_eq!(quote("$HOME"), "'$HOME'");
+        assert_eq!(quote("a\nb"), "'a\nb'");
+        assert_eq!(quote("*"), "'*'");
+        assert_eq!(quote("~"), "'~'");
+    }
+
+    #[test]
+    fn detect_password_prompt() {
+        assert!(is_password_prompt(b"Password:"));
+        assert!(is_password_prompt(b"doas (alice@host) password:"));
+        assert!(is_password_prompt(b"[sudo] password for alice:"));
+        assert!(is_password_prompt(b"Last login: today\r\nPassword:"));
+    }
+
+    #[test]
+    fn ignore_non_prompt() {
+        assert!(!is_password_prompt(b""));
+        assert!(!is_password_prompt(b"Login:"));
+        assert!(!is_password_prompt(
+            b"Password:\nsu: Authentication failure"
+        ));
+        assert!(!is_password_prompt(b"password expired\nlogin:"));
+    }
+
+    #[test]
+    fn escalate_with_c_locale() {
+        let command = Escalation::su().command_line("id", "M", true);
+        assert_eq!(
+            command,
+            "LC_ALL=C su root -c 'printf %s M_STARTED >&2; unset LC_ALL; id'"
+        );
+        let command = Escalation::doas()
+            .user("alice")
+            .command_line("id", "M", true);
+        assert_eq!(
+            command,
+            "LC_ALL=C doas -u alice sh -c 'printf %s M_STARTED >&2; unset LC_ALL; id'"
+        );
+        let command = Escalation::sudo().command_line("id", "M", false);
+        assert_eq!(
+            command,
+            "sudo -S -p M_PASSWORD: -u root -- sh -c 'printf %s M_STARTED >&2; id'"
+        );
+    }
+
+    #[test]
+    fn quote_leading_equals() {
+        assert_eq!(quote("=ls"), "'=ls'");
+        assert_eq!(quote("a=b"), "a=b");
+    }
+}
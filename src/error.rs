@@ -39,7 +39,7 @@ impl Ssh2Error {
     }
 
     pub(crate) fn unknown() -> Self {
-        Self::new(libc::c_int::min_value(), "unknown error")
+        Self::new(libc::c_int::MIN, "unknown error")
     }
 
     pub(crate) fn from_code(code: libc::c_int) -> Self {
@@ -120,7 +120,7 @@ impl Ssh2Error {
     }
 
     pub fn message(&self) -> &str {
-        &*self.msg
+        &self.msg
     }
 }
 
@@ -128,6 +128,14 @@ impl Ssh2Error {
 pub struct Error(ErrorKind);
 
 impl Error {
+    /// Return the libssh2 error code, if this error is reported by libssh2.
+    pub(crate) fn code(&self) -> Option<libc::c_int> {
+        match self.0 {
+            ErrorKind::Ssh2(ref err) => Some(err.code()),
+            _ => None,
+        }
+    }
+
     pub(crate) fn into_io_error(self) -> io::Error {
         match self.0 {
             ErrorKind::Io(err) => err,
@@ -0,0 +1,437 @@
+//! Automation of interactive programs, in the manner of `expect(1)`.
+
+use crate::{
+    channel::{Channel, ChannelStdin, ChannelStdout},
+    error::Result,
+};
+use regex::bytes::Regex;
+use std::{io, time::Duration};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// The default time to wait for a pattern.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The default maximum size of the unmatched output kept in the buffer.
+const DEFAULT_MAX_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// The time without output after which the prompt is assumed to be printed.
+const PROMPT_SETTLE_TIME: Duration = Duration::from_millis(500);
+
+/// A pattern to wait for in the output.
+#[derive(Debug, Clone)]
+pub struct Pattern(PatternKind);
+
+#[derive(Debug, Clone)]
+enum PatternKind {
+    Regex(Regex),
+    Eof,
+}
+
+impl Pattern {
+    /// Match the exact string.
+    pub fn literal(s: &str) -> Self {
+        Self::from_regex(Regex::new(&regex::escape(s)).expect("escaped literal must be valid"))
+    }
+
+    /// Match the regular expression.
+    pub fn regex(re: &str) -> std::result::Result<Self, regex::Error> {
+        Regex::new(re).map(Self::from_regex)
+    }
+
+    /// Match the end of the output.
+    pub fn eof() -> Self {
+        Self(PatternKind::Eof)
+    }
+
+    fn from_regex(re: Regex) -> Self {
+        Self(PatternKind::Regex(re))
+    }
+}
+
+impl From<&str> for Pattern {
+    fn from(s: &str) -> Self {
+        Self::literal(s)
+    }
+}
+
+impl From<Regex> for Pattern {
+    fn from(re: Regex) -> Self {
+        Self::from_regex(re)
+    }
+}
+
+/// The result of a successful `expect`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    /// The index of the matched pattern.
+    pub index: usize,
+    /// The output seen before the match.
+    pub before: String,
+    /// The matched output, which is empty for `Pattern::eof`.
+    pub matched: String,
+}
+
+/// A driver of an interactive program running on a channel.
+///
+/// The channel is typically a shell on a pseudo-terminal, on which the
+/// standard error is merged into the standard output. The output which is
+/// not consumed by a match is kept for the next `expect`.
+pub struct Expect {
+    stdin: ChannelStdin,
+    stdout: ChannelStdout,
+    buf: Vec<u8>,
+    eof: bool,
+    timeout: Duration,
+    max_buffer_size: usize,
+    line_ending: String,
+    prompt: Option<Pattern>,
+}
+
+impl Expect {
+    /// Create a driver of the program running on the channel.
+    pub fn new(channel: &Channel) -> Self {
+        let (stdin, stdout, _) = channel.split();
+        Self {
+            stdin,
+            stdout,
+            buf: Vec::new(),
+            eof: false,
+            timeout: DEFAULT_TIMEOUT,
+            max_buffer_size: DEFAULT_MAX_BUFFER_SIZE,
+            line_ending: "\n".to_owned(),
+            prompt: None,
+        }
+    }
+
+    /// Set the time to wait for a pattern, 30 seconds by default.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set the maximum size of the unmatched output kept in the buffer,
+    /// 1 MiB by default.
+    ///
+    /// The oldest output is discarded when the buffer grows beyond the limit.
+    pub fn max_buffer_size(mut self, max_buffer_size: usize) -> Self {
+        assert!(
+            max_buffer_size > 0,
+            "the maximum buffer size must be positive"
+        );
+        self.max_buffer_size = max_buffer_size;
+        self
+    }
+
+    /// Set the line ending appended by `send_line`, `"\n"` by default.
+    ///
+    /// Some appliances expect `"\r"` as the Enter key.
+    pub fn line_ending(mut self, line_ending: &str) -> Self {
+        self.line_ending = line_ending.to_owned();
+        self
+    }
+
+    /// Set the pattern of the prompt waited for by `expect_prompt` and `run`.
+    pub fn prompt(mut self, prompt: impl Into<Pattern>) -> Self {
+        self.prompt = Some(prompt.into());
+        self
+    }
+
+    /// Return the output received but not consumed by a match yet.
+    pub fn buffer(&self) -> String {
+        String::from_utf8_lossy(&self.buf).into_owned()
+    }
+
+    /// Send the data to the program.
+    pub async fn send(&mut self, data: impl AsRef<[u8]>) -> Result<()> {
+        self.stdin.write_all(data.as_ref()).await?;
+        self.stdin.flush().await?;
+        Ok(())
+    }
+
+    /// Send the line followed by the line ending to the program.
+    pub async fn send_line(&mut self, line: &str) -> Result<()> {
+        let data = format!("{}{}", line, self.line_ending);
+        self.send(data).await
+    }
+
+    /// Wait for the pattern to appear in the output.
+    pub async fn expect(&mut self, pattern: impl Into<Pattern>) -> Result<Match> {
+        self.expect_any(&[pattern.into()]).await
+    }
+
+    /// Wait for any of the patterns to appear in the output.
+    ///
+    /// The pattern matched at the earliest position wins, and the first one
+    /// in the slice wins the tie. The output up to the end of the match is
+    /// consumed. An error of the kind `TimedOut` is returned if no pattern
+    /// appears within the timeout, and `UnexpectedEof` if the output ends
+    /// without matching.
+    pub async fn expect_any(&mut self, patterns: &[Pattern]) -> Result<Match> {
+        let timeout = self.timeout;
+        match tokio::time::timeout(timeout, self.read_until_match(patterns)).await {
+            Ok(res) => res,
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("no pattern matched within {:?}", timeout),
+            )
+            .into()),
+        }
+    }
+
+    /// Wait for the prompt set by `prompt` or `detect_prompt`.
+    pub async fn expect_prompt(&mut self) -> Result<Match> {
+        let prompt = self.prompt.clone().ok_or_else(no_prompt)?;
+        self.expect_any(&[prompt]).await
+    }
+
+    /// Detect the prompt from the output, and use it as the prompt.
+    ///
+    /// A line ending is sent, and the last line printed before the output
+    /// pauses is taken as the prompt literally. Use `prompt` instead if the
+    /// prompt changes, e.g. with the current directory or the mode.
+    pub async fn detect_prompt(&mut self) -> Result<String> {
+        let line_ending = self.line_ending.clone();
+        self.send(line_ending).await?;
+
+        let timeout = self.timeout;
+        tokio::time::timeout(timeout, async {
+            loop {
+                match tokio::time::timeout(PROMPT_SETTLE_TIME, self.fill()).await {
+                    Ok(res) => {
+                        res?;
+                        if self.eof {
+                            return Err(unexpected_eof().into());
+                        }
+                    }
+                    Err(_) if self.last_line().trim().is_empty() => (),
+                    Err(_) => return Ok::<_, crate::Error>(()),
+                }
+            }
+        })
+        .await
+        .map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("no prompt detected within {:?}", timeout),
+            )
+        })??;
+
+        let prompt = self.last_line().trim().to_owned();
+        self.buf.clear();
+        self.prompt = Some(Pattern::literal(&prompt));
+        Ok(prompt)
+    }
+
+    /// Send the command line, and return its output printed before the
+    /// next prompt.
+    ///
+    /// The echo of the command line is removed from the output, and the
+    /// line endings are normalized to `"\n"`.
+    pub async fn run(&mut self, command: &str) -> Result<String> {
+        if self.prompt.is_none() {
+            return Err(no_prompt().into());
+        }
+        self.send_line(command).await?;
+        let m = self.expect_prompt().await?;
+
+        Ok(strip_echo(&m.before, command))
+    }
+
+    async fn read_until_match(&mut self, patterns: &[Pattern]) -> Result<Match> {
+        loop {
+            if let Some(m) = self.find(patterns) {
+                return Ok(m);
+            }
+            if self.eof {
+                return Err(unexpected_eof().into());
+            }
+            self.fill().await?;
+        }
+    }
+
+    /// Find the earliest match in the buffer, and consume it.
+    fn find(&mut self, patterns: &[Pattern]) -> Option<Match> {
+        find_match(&mut self.buf, self.eof, patterns)
+    }
+
+    /// Read the available output into the buffer.
+    async fn fill(&mut self) -> Result<()> {
+        let mut chunk = [0u8; 8192];
+        let n = self.stdout.read(&mut chunk).await?;
+        if n == 0 {
+            self.eof = true;
+            return Ok(());
+        }
+        self.buf.extend_from_slice(&chunk[..n]);
+        trim_buffer(&mut self.buf, self.max_buffer_size);
+        Ok(())
+    }
+
+    fn last_line(&self) -> String {
+        last_line(&self.buf)
+    }
+}
+
+/// Find the earliest match of the patterns in the buffer, and consume the
+/// output up to the end of the match.
+fn find_match(buf: &mut Vec<u8>, eof: bool, patterns: &[Pattern]) -> Option<Match> {
+    let mut found: Option<(usize, usize, usize)> = None;
+    for (index, pattern) in patterns.iter().enumerate() {
+        let (start, end) = match pattern.0 {
+            PatternKind::Regex(ref re) => match re.find(buf) {
+                Some(m) => (m.start(), m.end()),
+                None => continue,
+            },
+            PatternKind::Eof if eof => (buf.len(), buf.len()),
+            PatternKind::Eof => continue,
+        };
+        let earlier = match found {
+            Some((_, found_start, _)) => start < found_start,
+            None => true,
+        };
+        if earlier {
+            found = Some((index, start, end));
+        }
+    }
+
+    let (index, start, end) = found?;
+    let consumed: Vec<u8> = buf.drain(..end).collect();
+    Some(Match {
+        index,
+        before: String::from_utf8_lossy(&consumed[..start]).into_owned(),
+        matched: String::from_utf8_lossy(&consumed[start..]).into_owned(),
+    })
+}
+
+/// Discard the oldest output beyond the maximum size of the buffer.
+fn trim_buffer(buf: &mut Vec<u8>, max_buffer_size: usize) {
+    if buf.len() > max_buffer_size {
+        let excess = buf.len() - max_buffer_size;
+        buf.drain(..excess);
+    }
+}
+
+/// Return the last line in the buffer, which may be incomplete.
+fn last_line(buf: &[u8]) -> String {
+    let start = buf
+        .iter()
+        .rposition(|&b| b == b'\n' || b == b'\r')
+        .map_or(0, |i| i + 1);
+    String::from_utf8_lossy(&buf[start..]).into_owned()
+}
+
+/// Normalize the line endings of the output of the command, and remove
+/// the echo of the command line.
+fn strip_echo(output: &str, command: &str) -> String {
+    let output = output.replace("\r\n", "\n");
+    match output.split_once('\n') {
+        Some((echo, rest)) if echo.trim_end() == command => rest.to_owned(),
+        _ => output,
+    }
+}
+
+fn no_prompt() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "the prompt is not set")
+}
+
+fn unexpected_eof() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "the output ended without matching",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(buf: &str, eof: bool, patterns: &[Pattern]) -> (Option<Match>, String) {
+        let mut buf = buf.as_bytes().to_vec();
+        let m = find_match(&mut buf, eof, patterns);
+        (m, String::from_utf8(buf).unwrap())
+    }
+
+    fn matched(index: usize, before: &str, matched: &str) -> Option<Match> {
+        Some(Match {
+            index,
+            before: before.to_owned(),
+            matched: matched.to_owned(),
+        })
+    }
+
+    #[test]
+    fn earliest_match_wins() {
+        let patterns = [Pattern::literal("world"), Pattern::literal("hello")];
+        let (m, rest) = find("say hello world!", false, &patterns);
+        assert_eq!(m, matched(1, "say ", "hello"));
+        assert_eq!(rest, " world!");
+    }
+
+    #[test]
+    fn first_pattern_wins_tie() {
+        let patterns = [Pattern::literal("pass"), Pattern::literal("password:")];
+        let (m, rest) = find("password: ", false, &patterns);
+        assert_eq!(m, matched(0, "", "pass"));
+        assert_eq!(rest, "word: ");
+    }
+
+    #[test]
+    fn no_match_keeps_buffer() {
+        let (m, rest) = find("partial out", false, &[Pattern::literal("output")]);
+        assert_eq!(m, None);
+        assert_eq!(rest, "partial out");
+    }
+
+    #[test]
+    fn regex_and_literal() {
+        let patterns = [Pattern::literal("a.c"), Pattern::regex(r"\d+").unwrap()];
+        let (m, rest) = find("abc 42 a.c", false, &patterns);
+        assert_eq!(m, matched(1, "abc ", "42"));
+        assert_eq!(rest, " a.c");
+
+        // The literal escapes the metacharacters.
+        let (m, _) = find("abc a.c", false, &patterns);
+        assert_eq!(m, matched(0, "abc ", "a.c"));
+    }
+
+    #[test]
+    fn eof_pattern() {
+        let patterns = [Pattern::literal("$ "), Pattern::eof()];
+        let (m, _) = find("logout", false, &patterns);
+        assert_eq!(m, None);
+
+        let (m, rest) = find("logout", true, &patterns);
+        assert_eq!(m, matched(1, "logout", ""));
+        assert_eq!(rest, "");
+
+        // The output before EOF is matched first.
+        let (m, rest) = find("$ exit", true, &patterns);
+        assert_eq!(m, matched(0, "", "$ "));
+        assert_eq!(rest, "exit");
+    }
+
+    #[test]
+    fn strip_command_echo() {
+        assert_eq!(strip_echo("ls\r\na\r\nb\r\n", "ls"), "a\nb\n");
+        assert_eq!(strip_echo("ls  \r\na\r\n", "ls"), "a\n");
+        // The output is kept if the echo is disabled.
+        assert_eq!(strip_echo("a\r\nb\r\n", "ls"), "a\nb\n");
+        assert_eq!(strip_echo("", "ls"), "");
+    }
+
+    #[test]
+    fn trim_oldest_output() {
+        let mut buf = b"0123456789".to_vec();
+        trim_buffer(&mut buf, 4);
+        assert_eq!(buf, b"6789");
+        trim_buffer(&mut buf, 8);
+        assert_eq!(buf, b"6789");
+    }
+
+    #[test]
+    fn last_line_after_line_ending() {
+        assert_eq!(last_line(b"motd\r\nuser@host:~$ "), "user@host:~$ ");
+        assert_eq!(last_line(b"> "), "> ");
+        assert_eq!(last_line(b"done\n"), "");
+    }
+}
@@ -0,0 +1,75 @@
+//! The libssh2 functions that are not (yet) exposed by `libssh2-sys`.
+
+#![allow(non_camel_case_types)]
+
+use libssh2_sys as sys;
+
+pub(crate) type libssh2_trace_handler_func = extern "C" fn(
+    session: *mut sys::LIBSSH2_SESSION,
+    context: *mut libc::c_void,
+    data: *const libc::c_char,
+    length: libc::size_t,
+);
+
+pub(crate) type LIBSSH2_X11_OPEN_FUNC = extern "C" fn(
+    session: *mut sys::LIBSSH2_SESSION,
+    channel: *mut sys::LIBSSH2_CHANNEL,
+    shost: *const libc::c_char,
+    sport: libc::c_int,
+    abstract_: *mut *mut libc::c_void,
+);
+
+pub(crate) type LIBSSH2_AUTHAGENT_FUNC = extern "C" fn(
+    session: *mut sys::LIBSSH2_SESSION,
+    channel: *mut sys::LIBSSH2_CHANNEL,
+    abstract_: *mut *mut libc::c_void,
+);
+
+pub(crate) type LIBSSH2_SEND_FUNC = extern "C" fn(
+    socket: libc::c_int,
+    buffer: *const libc::c_void,
+    length: libc::size_t,
+    flags: libc::c_int,
+    abstract_: *mut *mut libc::c_void,
+) -> libc::ssize_t;
+
+pub(crate) type LIBSSH2_RECV_FUNC = extern "C" fn(
+    socket: libc::c_int,
+    buffer: *mut libc::c_void,
+    length: libc::size_t,
+    flags: libc::c_int,
+    abstract_: *mut *mut libc::c_void,
+) -> libc::ssize_t;
+
+pub(crate) const LIBSSH2_CALLBACK_X11: libc::c_int = 4;
+pub(crate) const LIBSSH2_CALLBACK_SEND: libc::c_int = 5;
+pub(crate) const LIBSSH2_CALLBACK_RECV: libc::c_int = 6;
+pub(crate) const LIBSSH2_CALLBACK_AUTHAGENT: libc::c_int = 7;
+
+extern "C" {
+    pub(crate) fn libssh2_trace_sethandler(
+        session: *mut sys::LIBSSH2_SESSION,
+        context: *mut libc::c_void,
+        callback: Option<libssh2_trace_handler_func>,
+    ) -> libc::c_int;
+
+    pub(crate) fn libssh2_session_callback_set(
+        session: *mut sys::LIBSSH2_SESSION,
+        cbtype: libc::c_int,
+        callback: *mut libc::c_void,
+    ) -> *mut libc::c_void;
+
+    pub(crate) fn libssh2_channel_signal_ex(
+        channel: *mut sys::LIBSSH2_CHANNEL,
+        signame: *const libc::c_char,
+        signame_len: libc::size_t,
+    ) -> libc::c_int;
+
+    pub(crate) fn libssh2_channel_x11_req_ex(
+        channel: *mut sys::LIBSSH2_CHANNEL,
+        single_connection: libc::c_int,
+        auth_proto: *const libc::c_char,
+        auth_cookie: *const libc::c_char,
+        screen_number: libc::c_int,
+    ) -> libc::c_int;
+}
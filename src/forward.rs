@@ -0,0 +1,608 @@
+//! Port forwarding over SSH.
+//!
+//! The forwarders serve the connections concurrently. The failures of the
+//! individual connections are logged at the `DEBUG` level, and do not stop
+//! the forwarding.
+
+#[cfg(unix)]
+use crate::channel::AuthAgentChannels;
+use crate::{
+    channel::{Channel, X11Channels},
+    error::Result,
+    instrument::instrument,
+    session::{Authenticated, BlockTimer, SessionInner},
+    socks, Session,
+};
+use futures::{
+    future::{poll_fn, Future},
+    stream::{self, FuturesUnordered, Stream, StreamExt},
+    task::{self, Poll},
+};
+use libssh2_sys as sys;
+#[cfg(unix)]
+use std::path::Path;
+use std::{cell::RefCell, fmt, net::SocketAddr, pin::Pin, ptr::NonNull, rc::Rc};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+use tokio::{
+    io::{self, AsyncRead, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+};
+use tracing::Instrument as _;
+
+/// Copy the data between the channel and the local stream in both
+/// directions, until both of them reach EOF.
+///
+/// Returns the number of bytes sent to and received from the channel, which
+/// are also logged when the connection is closed.
+pub(crate) async fn proxy<S>(channel: &mut Channel, stream: S) -> io::Result<(u64, u64)>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    let (mut reader, mut writer) = io::split(stream);
+    let (mut stdin, mut stdout, _) = channel.split();
+
+    let upstream = async {
+        let n = io::copy(&mut reader, &mut stdin).await?;
+        stdin.shutdown().await?;
+        Ok::<_, io::Error>(n)
+    };
+    let downstream = async {
+        let n = io::copy(&mut stdout, &mut writer).await?;
+        writer.shutdown().await?;
+        Ok::<_, io::Error>(n)
+    };
+    let (sent, received) = futures::try_join!(upstream, downstream)?;
+
+    channel.close().await.map_err(|err| err.into_io_error())?;
+    tracing::debug!(sent, received, "connection closed");
+    Ok((sent, received))
+}
+
+/// Serve the incoming connections concurrently, until the stream of the
+/// connections ends or fails.
+///
+/// The handler returns the span in which the connection is served, and the
+/// future serving it. When the stream ends, the connections in progress are
+/// waited for.
+async fn serve<S, C, F, Fut>(incoming: S, mut handler: F) -> Result<()>
+where
+    S: Stream<Item = Result<C>>,
+    F: FnMut(C) -> (tracing::Span, Fut),
+    Fut: Future<Output = Result<()>>,
+{
+    futures::pin_mut!(incoming);
+    let mut conns = FuturesUnordered::new();
+
+    loop {
+        tokio::select! {
+            res = incoming.next() => {
+                let conn = match res {
+                    Some(res) => res?,
+                    None => break,
+                };
+                let (span, conn) = handler(conn);
+                conns.push(
+                    async move {
+                        if let Err(err) = conn.await {
+                            tracing::debug!("connection failed: {}", err);
+                        }
+                    }
+                    .instrument(span),
+                );
+            }
+            _ = conns.next(), if !conns.is_empty() => (),
+        }
+    }
+
+    while conns.next().await.is_some() {}
+    Ok(())
+}
+
+/// A local port forwarder, as with `ssh -L`.
+///
+/// The connections accepted on the local listener are forwarded to the
+/// destination via direct-tcpip or direct-streamlocal channels.
+pub struct LocalForwarder {
+    listener: Listener,
+    target: Target,
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+enum Target {
+    Tcp { host: String, port: u16 },
+    StreamLocal(String),
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Tcp { host, port } => write!(f, "{}:{}", host, port),
+            Target::StreamLocal(path) => f.write_str(path),
+        }
+    }
+}
+
+impl Target {
+    async fn open(
+        &self,
+        session: &Session<Authenticated>,
+        peer: Option<SocketAddr>,
+    ) -> Result<Channel> {
+        match self {
+            Target::Tcp { host, port } => session.channel_direct_tcpip(host, *port, peer).await,
+            Target::StreamLocal(path) => session.channel_direct_streamlocal(path).await,
+        }
+    }
+}
+
+impl LocalForwarder {
+    /// Bind a local listener that forwards the connections to `host:port`.
+    pub async fn bind(addr: impl ToSocketAddrs, host: &str, port: u16) -> Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        Ok(Self::from_listener(listener, host, port))
+    }
+
+    /// Create a forwarder from the already bound listener.
+    pub fn from_listener(listener: TcpListener, host: &str, port: u16) -> Self {
+        Self {
+            listener: Listener::Tcp(listener),
+            target: Target::Tcp {
+                host: host.to_owned(),
+                port,
+            },
+        }
+    }
+
+    /// Bind a local listener that forwards the connections to the Unix
+    /// domain socket on the remote host.
+    pub async fn bind_streamlocal(addr: impl ToSocketAddrs, socket_path: &str) -> Result<Self> {
+        Ok(Self {
+            listener: Listener::Tcp(TcpListener::bind(addr).await?),
+            target: Target::StreamLocal(socket_path.to_owned()),
+        })
+    }
+
+    /// Bind a local Unix domain socket that forwards the connections to the
+    /// Unix domain socket on the remote host.
+    #[cfg(unix)]
+    pub fn bind_unix_streamlocal(path: impl AsRef<Path>, socket_path: &str) -> Result<Self> {
+        Ok(Self {
+            listener: Listener::Unix(UnixListener::bind(path)?),
+            target: Target::StreamLocal(socket_path.to_owned()),
+        })
+    }
+
+    /// Return the local address that this forwarder is bound to.
+    ///
+    /// This method fails if the forwarder is bound to a Unix domain socket.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        match self.listener {
+            Listener::Tcp(ref listener) => Ok(listener.local_addr()?),
+            #[cfg(unix)]
+            Listener::Unix(..) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the forwarder is bound to a Unix domain socket",
+            )
+            .into()),
+        }
+    }
+
+    /// Accept the connections and forward them through the session.
+    ///
+    /// This method returns only if the listener fails.
+    pub async fn run(mut self, session: &Session<Authenticated>) -> Result<()> {
+        let target = &self.target;
+        let incoming = stream::unfold(&mut self.listener, |listener| async {
+            let res = listener.accept().await.map_err(Into::into);
+            Some((res, listener))
+        });
+        serve(incoming, |(stream, peer)| {
+            let span = match peer {
+                Some(peer) => tracing::debug_span!("forward_local", %peer, %target),
+                None => tracing::debug_span!("forward_local", %target),
+            };
+            let conn = async move {
+                let mut channel = target.open(session, peer).await?;
+                stream.proxy(&mut channel).await?;
+                Ok(())
+            };
+            (span, conn)
+        })
+        .await
+    }
+}
+
+enum LocalStream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl LocalStream {
+    async fn proxy(self, channel: &mut Channel) -> io::Result<(u64, u64)> {
+        match self {
+            LocalStream::Tcp(stream) => proxy(channel, stream).await,
+            #[cfg(unix)]
+            LocalStream::Unix(stream) => proxy(channel, stream).await,
+        }
+    }
+}
+
+impl Listener {
+    async fn accept(&mut self) -> io::Result<(LocalStream, Option<SocketAddr>)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, peer) = listener.accept().await?;
+                Ok((LocalStream::Tcp(stream), Some(peer)))
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener) => {
+                let (stream, _) = listener.accept().await?;
+                Ok((LocalStream::Unix(stream), None))
+            }
+        }
+    }
+}
+
+/// A dynamic port forwarder acting as a SOCKS proxy server, as with `ssh -D`.
+///
+/// The CONNECT requests of SOCKS4, SOCKS4a and SOCKS5 without
+/// authentication are forwarded to the requested destinations via
+/// direct-tcpip channels.
+pub struct DynamicForwarder {
+    listener: TcpListener,
+}
+
+impl DynamicForwarder {
+    /// Bind a local listener for the SOCKS clients.
+    pub async fn bind(addr: impl ToSocketAddrs) -> Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        Ok(Self::from_listener(listener))
+    }
+
+    /// Create a forwarder from the already bound listener.
+    pub fn from_listener(listener: TcpListener) -> Self {
+        Self { listener }
+    }
+
+    /// Return the local address that this forwarder is bound to.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Accept the connections and forward them through the session.
+    ///
+    /// This method returns only if the listener fails.
+    pub async fn run(mut self, session: &Session<Authenticated>) -> Result<()> {
+        let incoming = stream::unfold(&mut self.listener, |listener| async {
+            let res = listener.accept().await.map_err(Into::into);
+            Some((res, listener))
+        });
+        serve(incoming, |(stream, peer)| {
+            let span = tracing::debug_span!("forward_dynamic", %peer);
+            (span, forward_socks(session, stream, peer))
+        })
+        .await
+    }
+}
+
+async fn forward_socks(
+    session: &Session<Authenticated>,
+    mut stream: TcpStream,
+    peer: SocketAddr,
+) -> Result<()> {
+    let request = socks::accept(&mut stream).await?;
+    tracing::debug!(host = &*request.host, port = request.port, "connect");
+
+    let mut channel = match session
+        .channel_direct_tcpip(&request.host, request.port, Some(peer))
+        .await
+    {
+        Ok(channel) => channel,
+        Err(err) => {
+            socks::reply(&mut stream, request.version, false).await?;
+            return Err(err);
+        }
+    };
+    socks::reply(&mut stream, request.version, true).await?;
+
+    proxy(&mut channel, stream).await?;
+    Ok(())
+}
+
+/// A listener on the remote host, created by `Session::forward_listen`.
+///
+/// The connections to the listening port are yielded as the channels
+/// from the implementation of `Stream`.
+pub struct RemoteListener {
+    raw: NonNull<sys::LIBSSH2_LISTENER>,
+    port: u16,
+    sess: Rc<RefCell<SessionInner>>,
+    cancelled: bool,
+    timer: BlockTimer,
+}
+
+impl Drop for RemoteListener {
+    fn drop(&mut self) {
+        if !self.cancelled {
+            unsafe {
+                // FIXME: should we handle EAGAIN at here?
+                sys::libssh2_channel_forward_cancel(self.raw.as_ptr());
+            }
+        }
+    }
+}
+
+impl RemoteListener {
+    pub(crate) fn new(
+        raw: NonNull<sys::LIBSSH2_LISTENER>,
+        port: u16,
+        sess: Rc<RefCell<SessionInner>>,
+    ) -> Self {
+        Self {
+            raw,
+            port,
+            sess,
+            cancelled: false,
+            timer: BlockTimer::default(),
+        }
+    }
+
+    /// Return the port that the remote host is listening on.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Stop listening on the remote host.
+    pub async fn cancel(mut self) -> Result<()> {
+        let span = op_span!(self.sess.borrow(), "forward_cancel", port = self.port);
+        let raw = self.raw;
+        let sess = self.sess.clone();
+        let timer = BlockTimer::default();
+        instrument(
+            span,
+            poll_fn(|cx| {
+                sess.borrow_mut().poll_with(cx, &timer, |sess| {
+                    sess.rc(unsafe { sys::libssh2_channel_forward_cancel(raw.as_ptr()) })
+                        .map(drop)
+                })
+            }),
+        )
+        .await?;
+        self.cancelled = true;
+        Ok(())
+    }
+
+    /// Accept the connections and forward them to the local address.
+    ///
+    /// This method returns only if the listener fails.
+    pub async fn forward_to(self, addr: SocketAddr) -> Result<()> {
+        let port = self.port;
+        serve(self, |mut channel| {
+            let span = tracing::debug_span!("forward_remote", port, %addr);
+            let conn = async move {
+                let stream = TcpStream::connect(addr).await?;
+                proxy(&mut channel, stream).await?;
+                Ok(())
+            };
+            (span, conn)
+        })
+        .await
+    }
+}
+
+impl Stream for RemoteListener {
+    type Item = Result<Channel>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<Option<Self::Item>> {
+        let me = self.get_mut();
+        let _enter = tracing::trace_span!("forward_accept", port = me.port).entered();
+        let raw = me.raw;
+        let mut sess = me.sess.borrow_mut();
+        let res = sess.poll_with(cx, &me.timer, |sess| {
+            NonNull::new(unsafe { sys::libssh2_channel_forward_accept(raw.as_ptr()) })
+                .ok_or_else(|| sess.last_error())
+        });
+        res.map(|res| {
+            Some(res.map(|raw| {
+                let id = sess.next_channel_id();
+                Channel::new(raw, id, me.sess.clone())
+            }))
+        })
+    }
+}
+
+impl X11Channels {
+    /// Forward the X11 channels to the local X display, e.g. `:0` or `localhost:10.0`.
+    ///
+    /// The display defaults to the `DISPLAY` environment variable. This
+    /// method returns when the stream of the X11 channels ends.
+    pub async fn forward_to_display(self, display: Option<&str>) -> Result<()> {
+        let display = match display {
+            Some(display) => display.to_owned(),
+            None => std::env::var("DISPLAY").map_err(|_| {
+                io::Error::new(io::ErrorKind::NotFound, "the DISPLAY variable is not set")
+            })?,
+        };
+        let x11_display = &*display;
+        serve(self, |mut channel| {
+            let span = tracing::debug_span!("forward_x11", display = x11_display);
+            let conn = async move {
+                let stream = connect_display(x11_display).await?;
+                stream.proxy(&mut channel).await?;
+                Ok(())
+            };
+            (span, conn)
+        })
+        .await
+    }
+}
+
+#[cfg(unix)]
+impl AuthAgentChannels {
+    /// Forward the agent channels to the local agent listening on the Unix
+    /// domain socket.
+    ///
+    /// The socket defaults to the `SSH_AUTH_SOCK` environment variable. This
+    /// method returns when the stream of the agent channels ends.
+    pub async fn forward_to_agent(self, socket_path: Option<&Path>) -> Result<()> {
+        let socket_path = match socket_path {
+            Some(path) => path.to_owned(),
+            None => std::env::var_os("SSH_AUTH_SOCK")
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        "the SSH_AUTH_SOCK variable is not set",
+                    )
+                })?
+                .into(),
+        };
+        let socket_path = &*socket_path;
+        serve(self, |mut channel| {
+            let span = tracing::debug_span!("forward_auth_agent", path = %socket_path.display());
+            let conn = async move {
+                let stream = UnixStream::connect(socket_path).await?;
+                proxy(&mut channel, stream).await?;
+                Ok(())
+            };
+            (span, conn)
+        })
+        .await
+    }
+}
+
+/// The base of the TCP ports of the X displays.
+const X11_BASE_PORT: u16 = 6000;
+
+/// The directory of the Unix domain sockets of the local X displays.
+#[cfg(unix)]
+const X11_UNIX_DIR: &str = "/tmp/.X11-unix";
+
+/// The address of an X display.
+#[derive(Debug, PartialEq, Eq)]
+enum DisplayAddr {
+    #[cfg(unix)]
+    Unix(String),
+    Tcp(String, u16),
+}
+
+/// Parse the X display in the form of `[host]:display[.screen]`.
+fn parse_display(display: &str) -> io::Result<DisplayAddr> {
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid X display: {}", display),
+        )
+    };
+    let (host, number) = display.rsplit_once(':').ok_or_else(invalid)?;
+    let number = number.split('.').next().unwrap_or_default();
+    let number: u16 = number.parse().map_err(|_| invalid())?;
+
+    #[cfg(unix)]
+    {
+        if host.is_empty() || host == "unix" {
+            return Ok(DisplayAddr::Unix(format!("{}/X{}", X11_UNIX_DIR, number)));
+        }
+    }
+
+    let port = X11_BASE_PORT.checked_add(number).ok_or_else(invalid)?;
+    let host = if host.is_empty() { "localhost" } else { host };
+    Ok(DisplayAddr::Tcp(host.to_owned(), port))
+}
+
+/// Connect to the local X display, in the form of `[host]:display[.screen]`.
+async fn connect_display(display: &str) -> io::Result<LocalStream> {
+    match parse_display(display)? {
+        #[cfg(unix)]
+        DisplayAddr::Unix(path) => Ok(LocalStream::Unix(UnixStream::connect(path).await?)),
+        DisplayAddr::Tcp(host, port) => {
+            Ok(LocalStream::Tcp(TcpStream::connect((&*host, port)).await?))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn parse_unix_display() {
+        assert_eq!(
+            parse_display(":0").unwrap(),
+            DisplayAddr::Unix("/tmp/.X11-unix/X0".into())
+        );
+        assert_eq!(
+            parse_display("unix:1.0").unwrap(),
+            DisplayAddr::Unix("/tmp/.X11-unix/X1".into())
+        );
+    }
+
+    #[test]
+    fn parse_tcp_display() {
+        assert_eq!(
+            parse_display("localhost:10.0").unwrap(),
+            DisplayAddr::Tcp("localhost".into(), 6010)
+        );
+        assert_eq!(
+            parse_display("192.0.2.1:2").unwrap(),
+            DisplayAddr::Tcp("192.0.2.1".into(), 6002)
+        );
+        assert_eq!(
+            parse_display("::1:0").unwrap(),
+            DisplayAddr::Tcp("::1".into(), 6000)
+        );
+    }
+
+    #[test]
+    fn parse_invalid_display() {
+        for display in &[
+            "",
+            "localhost",
+            "localhost:",
+            "localhost:x",
+            "localhost:65535",
+        ] {
+            let err = parse_display(display).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{}", display);
+        }
+    }
+
+    #[tokio::test]
+    async fn connect_tcp_display() {
+        // The ephemeral ports may be below the base port, so a free port is
+        // searched among the displays unlikely to be used by an X server.
+        let mut listener = None;
+        for number in 100..200 {
+            if let Ok(l) = TcpListener::bind(("127.0.0.1", X11_BASE_PORT + number)).await {
+                listener = Some((l, number));
+                break;
+            }
+        }
+        let (mut listener, number) = listener.expect("no free port for a display");
+        let port = listener.local_addr().unwrap().port();
+        let display = format!("127.0.0.1:{}.0", number);
+
+        let (stream, accepted) = futures::join!(connect_display(&display), listener.accept());
+        accepted.unwrap();
+        match stream.unwrap() {
+            LocalStream::Tcp(stream) => {
+                assert_eq!(stream.peer_addr().unwrap().port(), port);
+            }
+            #[cfg(unix)]
+            LocalStream::Unix(..) => panic!("connected to a Unix domain socket"),
+        }
+    }
+
+    #[tokio::test]
+    async fn connect_invalid_display() {
+        let err = connect_display("localhost").await.err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,42 @@
+//! `tracing` instrumentation of the asynchronous operations.
+
+use crate::error::Result;
+use std::{future::Future, time::Instant};
+use tracing::Instrument as _;
+
+/// Create a `DEBUG` level span for an operation on the specified session.
+///
+/// The span is filled with the connection metadata of the session, and
+/// has the fields that are recorded by `instrument` on completion.
+macro_rules! op_span {
+    ($sess:expr, $name:literal $(, $($fields:tt)+)?) => {{
+        let span = tracing::debug_span!(
+            $name,
+            $($($fields)+,)?
+            remote_addr = tracing::field::Empty,
+            username = tracing::field::Empty,
+            elapsed_us = tracing::field::Empty,
+            error.code = tracing::field::Empty,
+        );
+        $sess.record_metadata(&span);
+        span
+    }};
+}
+
+/// Run the future within the provided span, and record its duration and
+/// the error code when it completes.
+pub(crate) async fn instrument<F, T>(span: tracing::Span, future: F) -> Result<T>
+where
+    F: Future<Output = Result<T>>,
+{
+    let start = Instant::now();
+    let res = future.instrument(span.clone()).await;
+    span.record("elapsed_us", start.elapsed().as_micros() as u64);
+    if let Err(ref err) = res {
+        if let Some(code) = err.code() {
+            span.record("error.code", code);
+        }
+        tracing::debug!(parent: &span, "failed: {}", err);
+    }
+    res
+}
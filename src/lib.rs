@@ -1,14 +1,33 @@
 //! libssh2 bindings library, focuses on the interoperability with Tokio.
 
+#[macro_use]
+mod instrument;
+
 pub mod auth;
 mod channel;
+pub mod command;
 mod error;
+pub mod expect;
+mod ffi;
+pub mod forward;
+pub mod lines;
+pub mod memory;
+pub mod pty;
 mod session;
 pub mod sftp;
+pub mod shell;
+mod socks;
+mod stats;
+#[cfg(unix)]
+pub mod terminal;
 mod util;
 
 pub use crate::{
-    channel::{Channel, Stream},
+    channel::{
+        AuthAgentChannels, Channel, ChannelStderr, ChannelStdin, ChannelStdout, ExitStatus,
+        ExtendedData, PipeStatus, ReadWindow, Stream, WriteWindow, X11Channels,
+    },
     error::{Error, Result},
-    session::Session,
+    session::{AuthError, Authenticated, Connected, Session, State, TraceFlags, Unconnected},
+    stats::Stats,
 };
@@ -0,0 +1,243 @@
+//! Line-oriented reading of the output of remote processes.
+
+use crate::{
+    channel::{Channel, ChannelStderr, ChannelStdout},
+    error::Result,
+};
+use futures::{
+    stream::Stream,
+    task::{self, Poll},
+};
+use std::{collections::VecDeque, mem, pin::Pin};
+use tokio::io::AsyncRead;
+
+/// The default maximum length of a line in bytes.
+const DEFAULT_MAX_LINE_LENGTH: usize = 64 * 1024;
+
+/// The output stream that a line is read from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Source {
+    /// The standard output.
+    Stdout,
+    /// The standard error.
+    Stderr,
+}
+
+/// A line of the output of a remote process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    /// The output stream that the line is read from.
+    pub source: Source,
+    /// The content of the line without the line terminator.
+    ///
+    /// The invalid UTF-8 sequences are replaced with `U+FFFD`.
+    pub text: String,
+}
+
+/// A stream of the lines of the standard output and error of a channel.
+///
+/// The lines are yielded in the order they arrive. A line longer than the
+/// maximum length is split into multiple lines, and the last line without
+/// the line terminator is yielded at EOF.
+pub struct Lines {
+    stdout: LineReader<ChannelStdout>,
+    stderr: LineReader<ChannelStderr>,
+    max_line_length: usize,
+    queue: VecDeque<Line>,
+}
+
+impl Lines {
+    /// Create a stream of the lines read from the channel.
+    pub fn new(channel: &Channel) -> Self {
+        let (_, stdout, stderr) = channel.split();
+        Self {
+            stdout: LineReader::new(stdout, Source::Stdout),
+            stderr: LineReader::new(stderr, Source::Stderr),
+            max_line_length: DEFAULT_MAX_LINE_LENGTH,
+            queue: VecDeque::new(),
+        }
+    }
+
+    /// Set the maximum length of a line in bytes, 64 KiB by default.
+    pub fn max_line_length(mut self, max_line_length: usize) -> Self {
+        assert!(
+            max_line_length > 0,
+            "the maximum line length must be positive"
+        );
+        self.max_line_length = max_line_length;
+        self
+    }
+}
+
+impl Stream for Lines {
+    type Item = Result<Line>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<Option<Self::Item>> {
+        let me = self.get_mut();
+        loop {
+            if let Some(line) = me.queue.pop_front() {
+                return Poll::Ready(Some(Ok(line)));
+            }
+            if me.stdout.eof && me.stderr.eof {
+                return Poll::Ready(None);
+            }
+
+            let stdout = me.stdout.poll_fill(cx, me.max_line_length, &mut me.queue);
+            let stderr = me.stderr.poll_fill(cx, me.max_line_length, &mut me.queue);
+            match (stdout, stderr) {
+                (Poll::Ready(Err(err)), _) | (_, Poll::Ready(Err(err))) => {
+                    return Poll::Ready(Some(Err(err)));
+                }
+                (Poll::Pending, Poll::Pending) => return Poll::Pending,
+                _ => (),
+            }
+        }
+    }
+}
+
+struct LineReader<R> {
+    reader: R,
+    source: Source,
+    buf: Vec<u8>,
+    eof: bool,
+}
+
+impl<R: AsyncRead + Unpin> LineReader<R> {
+    fn new(reader: R, source: Source) -> Self {
+        Self {
+            reader,
+            source,
+            buf: Vec::new(),
+            eof: false,
+        }
+    }
+
+    /// Read the available data, and push the complete lines into the queue.
+    fn poll_fill(
+        &mut self,
+        cx: &mut task::Context<'_>,
+        max_line_length: usize,
+        queue: &mut VecDeque<Line>,
+    ) -> Poll<Result<()>> {
+        if self.eof {
+            return Poll::Pending;
+        }
+
+        let mut chunk = [0u8; 8192];
+        let n = match Pin::new(&mut self.reader).poll_read(cx, &mut chunk) {
+            Poll::Ready(Ok(n)) => n,
+            Poll::Ready(Err(err)) => return Poll::Ready(Err(err.into())),
+            Poll::Pending => return Poll::Pending,
+        };
+
+        if n == 0 {
+            self.eof = true;
+            if !self.buf.is_empty() {
+                let line = mem::take(&mut self.buf);
+                queue.push_back(self.line(&line));
+            }
+            return Poll::Ready(Ok(()));
+        }
+
+        for &b in &chunk[..n] {
+            if b == b'\n' {
+                let mut line = mem::take(&mut self.buf);
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                queue.push_back(self.line(&line));
+            } else {
+                // The line is split only when it continues, so that a line
+                // of exactly the maximum length is not followed by an empty one.
+                if self.buf.len() >= max_line_length {
+                    let line = mem::take(&mut self.buf);
+                    queue.push_back(self.line(&line));
+                }
+                self.buf.push(b);
+            }
+        }
+
+        Poll::Ready(Ok(()))
+    }
+
+    fn line(&self, line: &[u8]) -> Line {
+        Line {
+            source: self.source,
+            text: String::from_utf8_lossy(line).into_owned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Read all the lines from the data with the maximum line length.
+    fn read_lines(data: &[u8], max_line_length: usize) -> Vec<String> {
+        let waker = futures::task::noop_waker();
+        let mut cx = task::Context::from_waker(&waker);
+        let mut reader = LineReader::new(data, Source::Stdout);
+        let mut queue = VecDeque::new();
+        while !reader.eof {
+            match reader.poll_fill(&mut cx, max_line_length, &mut queue) {
+                Poll::Ready(res) => res.unwrap(),
+                Poll::Pending => unreachable!("the slice never blocks"),
+            }
+        }
+        queue.into_iter().map(|line| line.text).collect()
+    }
+
+    #[test]
+    fn split_lines() {
+        assert_eq!(read_lines(b"foo\nbar\n", 16), ["foo", "bar"]);
+    }
+
+    #[test]
+    fn strip_carriage_return() {
+        assert_eq!(read_lines(b"foo\r\nbar\rbaz\n", 16), ["foo", "bar\rbaz"]);
+    }
+
+    #[test]
+    fn keep_empty_lines() {
+        assert_eq!(read_lines(b"\n\nfoo\n\n", 16), ["", "", "foo", ""]);
+    }
+
+    #[test]
+    fn yield_last_line_at_eof() {
+        assert_eq!(read_lines(b"foo\nbar", 16), ["foo", "bar"]);
+        assert!(read_lines(b"", 16).is_empty());
+    }
+
+    #[test]
+    fn split_long_lines() {
+        assert_eq!(read_lines(b"abcdefg\nhi\n", 3), ["abc", "def", "g", "hi"]);
+        assert_eq!(read_lines(b"abcd", 3), ["abc", "d"]);
+    }
+
+    #[test]
+    fn no_empty_line_after_max_length() {
+        assert_eq!(read_lines(b"abcdef\nghi\n", 3), ["abc", "def", "ghi"]);
+    }
+
+    #[test]
+    fn replace_invalid_utf8() {
+        assert_eq!(read_lines(b"a\xffb\n", 16), ["a\u{fffd}b"]);
+    }
+
+    #[test]
+    fn tag_source() {
+        let waker = futures::task::noop_waker();
+        let mut cx = task::Context::from_waker(&waker);
+        let mut reader = LineReader::new(&b"oops\n"[..], Source::Stderr);
+        let mut queue = VecDeque::new();
+        let res = reader.poll_fill(&mut cx, 16, &mut queue);
+        assert!(matches!(res, Poll::Ready(Ok(()))));
+        assert_eq!(
+            queue.pop_front(),
+            Some(Line {
+                source: Source::Stderr,
+                text: "oops".to_owned(),
+            })
+        );
+    }
+}
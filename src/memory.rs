@@ -0,0 +1,384 @@
+//! Custom allocators and memory accounting of sessions.
+
+use crate::session::SessionContext;
+use std::{
+    ptr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+/// The allocator used by libssh2 for the heap memory of a session.
+///
+/// The methods follow the semantics of `malloc(3)`, `realloc(3)` and
+/// `free(3)`. A null pointer returned from `alloc` or `realloc` is reported
+/// by libssh2 as an allocation failure of the running operation.
+pub trait Allocator: 'static {
+    /// Allocate a memory block of the specified size.
+    ///
+    /// # Safety
+    ///
+    /// The returned pointer must be suitably aligned for any kind of variable.
+    unsafe fn alloc(&self, size: usize) -> *mut u8;
+
+    /// Change the size of a memory block allocated by this allocator.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be null or a block returned from this allocator.
+    unsafe fn realloc(&self, ptr: *mut u8, size: usize) -> *mut u8;
+
+    /// Release a memory block allocated by this allocator.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be null or a block returned from this allocator.
+    unsafe fn free(&self, ptr: *mut u8);
+}
+
+impl<A: ?Sized + Allocator> Allocator for Arc<A> {
+    unsafe fn alloc(&self, size: usize) -> *mut u8 {
+        (**self).alloc(size)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, size: usize) -> *mut u8 {
+        (**self).realloc(ptr, size)
+    }
+
+    unsafe fn free(&self, ptr: *mut u8) {
+        (**self).free(ptr)
+    }
+}
+
+impl<A: ?Sized + Allocator> Allocator for Box<A> {
+    unsafe fn alloc(&self, size: usize) -> *mut u8 {
+        (**self).alloc(size)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, size: usize) -> *mut u8 {
+        (**self).realloc(ptr, size)
+    }
+
+    unsafe fn free(&self, ptr: *mut u8) {
+        (**self).free(ptr)
+    }
+}
+
+/// The size of the header storing the block size, which keeps the
+/// alignment guaranteed by `malloc(3)`.
+const HEADER_SIZE: usize = 16;
+
+/// An `Allocator` that counts the heap usage and optionally applies an upper limit.
+///
+/// The memory is obtained from `malloc(3)`. Share it with the session
+/// through an `Arc` to inspect the usage while the session is alive.
+#[derive(Debug, Default)]
+pub struct Accounting {
+    usage: AtomicUsize,
+    peak: AtomicUsize,
+    limit: Option<usize>,
+    failures: AtomicUsize,
+}
+
+impl Accounting {
+    /// Create an `Accounting` without any limit.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an `Accounting` that refuses the allocations beyond the
+    /// specified number of bytes.
+    pub fn with_limit(limit: usize) -> Self {
+        Self {
+            limit: Some(limit),
+            ..Self::default()
+        }
+    }
+
+    /// Return the number of bytes currently allocated.
+    pub fn usage(&self) -> usize {
+        self.usage.load(Ordering::Relaxed)
+    }
+
+    /// Return the highest number of bytes allocated at once.
+    pub fn peak(&self) -> usize {
+        self.peak.load(Ordering::Relaxed)
+    }
+
+    /// Return the upper limit of allocated bytes, if any.
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// Return the number of allocations refused due to the limit.
+    pub fn failures(&self) -> usize {
+        self.failures.load(Ordering::Relaxed)
+    }
+
+    fn reserve(&self, size: usize) -> bool {
+        let limit = self.limit;
+        let res = self
+            .usage
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |usage| {
+                let usage = usage.checked_add(size)?;
+                match limit {
+                    Some(limit) if usage > limit => None,
+                    _ => Some(usage),
+                }
+            });
+        match res {
+            Ok(prev) => {
+                self.peak.fetch_max(prev + size, Ordering::Relaxed);
+                true
+            }
+            Err(..) => {
+                self.failures.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+        }
+    }
+
+    fn release(&self, size: usize) {
+        self.usage.fetch_sub(size, Ordering::Relaxed);
+    }
+}
+
+impl Allocator for Accounting {
+    unsafe fn alloc(&self, size: usize) -> *mut u8 {
+        if !self.reserve(size) {
+            return ptr::null_mut();
+        }
+
+        let base = libc::malloc(HEADER_SIZE + size) as *mut u8;
+        if base.is_null() {
+            self.release(size);
+            return ptr::null_mut();
+        }
+        (base as *mut usize).write(size);
+
+        base.add(HEADER_SIZE)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, size: usize) -> *mut u8 {
+        if ptr.is_null() {
+            return self.alloc(size);
+        }
+
+        let base = ptr.sub(HEADER_SIZE);
+        let old_size = (base as *const usize).read();
+        if size > old_size && !self.reserve(size - old_size) {
+            return ptr::null_mut();
+        }
+
+        let base = libc::realloc(base as *mut libc::c_void, HEADER_SIZE + size) as *mut u8;
+        if base.is_null() {
+            if size > old_size {
+                self.release(size - old_size);
+            }
+            return ptr::null_mut();
+        }
+        if size < old_size {
+            self.release(old_size - size);
+        }
+        (base as *mut usize).write(size);
+
+        base.add(HEADER_SIZE)
+    }
+
+    unsafe fn free(&self, ptr: *mut u8) {
+        if ptr.is_null() {
+            return;
+        }
+
+        let base = ptr.sub(HEADER_SIZE);
+        self.release((base as *const usize).read());
+        libc::free(base as *mut libc::c_void);
+    }
+}
+
+/// Return the allocator from the abstract pointer passed to the callback functions.
+///
+/// The callback functions are registered only when the allocator is specified.
+unsafe fn from_abstract<'a>(abstract_: *mut *mut libc::c_void) -> &'a dyn Allocator {
+    let context = &*(*abstract_ as *const SessionContext);
+    match context.allocator {
+        Some(ref allocator) => &**allocator,
+        None => unreachable!("the allocator callbacks are registered without an allocator"),
+    }
+}
+
+pub(crate) extern "C" fn alloc_func(
+    size: libc::size_t,
+    abstract_: *mut *mut libc::c_void,
+) -> *mut libc::c_void {
+    unsafe { from_abstract(abstract_).alloc(size) as *mut libc::c_void }
+}
+
+pub(crate) extern "C" fn free_func(ptr: *mut libc::c_void, abstract_: *mut *mut libc::c_void) {
+    unsafe { from_abstract(abstract_).free(ptr as *mut u8) }
+}
+
+pub(crate) extern "C" fn realloc_func(
+    ptr: *mut libc::c_void,
+    size: libc::size_t,
+    abstract_: *mut *mut libc::c_void,
+) -> *mut libc::c_void {
+    unsafe { from_abstract(abstract_).realloc(ptr as *mut u8, size) as *mut libc::c_void }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Session;
+
+    /// Call the callbacks of libssh2 with a session context holding the allocator.
+    struct Callbacks {
+        context: Box<SessionContext>,
+    }
+
+    impl Callbacks {
+        fn new(accounting: &Arc<Accounting>) -> Self {
+            let allocator = Box::new(accounting.clone()) as Box<dyn Allocator>;
+            Self {
+                context: Box::new(SessionContext::new(Some(allocator))),
+            }
+        }
+
+        fn abstract_(&mut self) -> *mut libc::c_void {
+            &*self.context as *const SessionContext as *mut libc::c_void
+        }
+
+        fn alloc(&mut self, size: usize) -> *mut libc::c_void {
+            let mut abstract_ = self.abstract_();
+            alloc_func(size, &mut abstract_)
+        }
+
+        fn realloc(&mut self, ptr: *mut libc::c_void, size: usize) -> *mut libc::c_void {
+            let mut abstract_ = self.abstract_();
+            realloc_func(ptr, size, &mut abstract_)
+        }
+
+        fn free(&mut self, ptr: *mut libc::c_void) {
+            let mut abstract_ = self.abstract_();
+            free_func(ptr, &mut abstract_)
+        }
+    }
+
+    #[test]
+    fn count_alloc_and_free() {
+        let accounting = Arc::new(Accounting::new());
+        let mut callbacks = Callbacks::new(&accounting);
+
+        let a = callbacks.alloc(100);
+        let b = callbacks.alloc(28);
+        assert!(!a.is_null() && !b.is_null());
+        assert_eq!(a as usize % HEADER_SIZE, 0);
+        assert_eq!(accounting.usage(), 128);
+        assert_eq!(accounting.peak(), 128);
+
+        callbacks.free(a);
+        assert_eq!(accounting.usage(), 28);
+        callbacks.free(b);
+        callbacks.free(ptr::null_mut());
+        assert_eq!(accounting.usage(), 0);
+        assert_eq!(accounting.peak(), 128);
+        assert_eq!(accounting.failures(), 0);
+    }
+
+    #[test]
+    fn realloc_keeps_contents() {
+        let accounting = Arc::new(Accounting::new());
+        let mut callbacks = Callbacks::new(&accounting);
+
+        let ptr = callbacks.alloc(4) as *mut u8;
+        unsafe { ptr::copy_nonoverlapping(b"abcd".as_ptr(), ptr, 4) };
+
+        let ptr = callbacks.realloc(ptr as *mut libc::c_void, 4096) as *mut u8;
+        assert!(!ptr.is_null());
+        assert_eq!(unsafe { std::slice::from_raw_parts(ptr, 4) }, b"abcd");
+        assert_eq!(accounting.usage(), 4096);
+        assert_eq!(accounting.peak(), 4096);
+
+        callbacks.free(ptr as *mut libc::c_void);
+        assert_eq!(accounting.usage(), 0);
+    }
+
+    #[test]
+    fn realloc_null_allocates() {
+        let accounting = Arc::new(Accounting::new());
+        let mut callbacks = Callbacks::new(&accounting);
+
+        let ptr = callbacks.realloc(ptr::null_mut(), 64);
+        assert!(!ptr.is_null());
+        assert_eq!(accounting.usage(), 64);
+
+        callbacks.free(ptr);
+        assert_eq!(accounting.usage(), 0);
+    }
+
+    #[test]
+    fn realloc_shrinks() {
+        let accounting = Arc::new(Accounting::new());
+        let mut callbacks = Callbacks::new(&accounting);
+
+        let ptr = callbacks.alloc(1000);
+        let ptr = callbacks.realloc(ptr, 10);
+        assert!(!ptr.is_null());
+        assert_eq!(accounting.usage(), 10);
+        assert_eq!(accounting.peak(), 1000);
+
+        // The released size is the shrunk one.
+        callbacks.free(ptr);
+        assert_eq!(accounting.usage(), 0);
+    }
+
+    #[test]
+    fn refuse_beyond_limit() {
+        let accounting = Arc::new(Accounting::with_limit(100));
+        let mut callbacks = Callbacks::new(&accounting);
+        assert_eq!(accounting.limit(), Some(100));
+
+        let a = callbacks.alloc(60);
+        assert!(!a.is_null());
+        assert!(callbacks.alloc(41).is_null());
+        assert_eq!(accounting.failures(), 1);
+        assert_eq!(accounting.usage(), 60);
+
+        let b = callbacks.alloc(40);
+        assert!(!b.is_null());
+        assert_eq!(accounting.usage(), 100);
+        callbacks.free(b);
+
+        // A refused realloc leaves the original block untouched.
+        assert!(callbacks.realloc(a, 101).is_null());
+        assert_eq!(accounting.failures(), 2);
+        assert_eq!(accounting.usage(), 60);
+        let a = callbacks.realloc(a, 100);
+        assert!(!a.is_null());
+        assert_eq!(accounting.usage(), 100);
+        assert_eq!(accounting.peak(), 100);
+
+        callbacks.free(a);
+        assert_eq!(accounting.usage(), 0);
+    }
+
+    #[test]
+    fn release_session_memory() {
+        let accounting = Arc::new(Accounting::new());
+        let session = Session::with_allocator(accounting.clone()).unwrap();
+        assert!(accounting.usage() > 0);
+
+        drop(session);
+        assert_eq!(accounting.usage(), 0);
+        assert!(accounting.peak() > 0);
+    }
+
+    #[test]
+    fn fail_session_beyond_limit() {
+        let accounting = Arc::new(Accounting::with_limit(16));
+        assert!(Session::with_allocator(accounting.clone()).is_err());
+        assert!(accounting.failures() > 0);
+        assert_eq!(accounting.usage(), 0);
+    }
+}
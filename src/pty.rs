@@ -0,0 +1,239 @@
+//! Pseudo-terminal allocation.
+
+/// The opcodes of the terminal modes, defined in RFC 4254 section 8.
+#[allow(non_camel_case_types)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum TerminalMode {
+    /// Interrupt character.
+    VINTR = 1,
+    /// Quit character.
+    VQUIT = 2,
+    /// Erase the character to the left of the cursor.
+    VERASE = 3,
+    /// Kill the current input line.
+    VKILL = 4,
+    /// End-of-file character.
+    VEOF = 5,
+    /// End-of-line character in addition to carriage return and/or linefeed.
+    VEOL = 6,
+    /// Additional end-of-line character.
+    VEOL2 = 7,
+    /// Continue paused output.
+    VSTART = 8,
+    /// Pause output.
+    VSTOP = 9,
+    /// Suspend the current program.
+    VSUSP = 10,
+    /// Another suspend character.
+    VDSUSP = 11,
+    /// Reprint the current input line.
+    VREPRINT = 12,
+    /// Erase a word left of the cursor.
+    VWERASE = 13,
+    /// Enter the next character typed literally.
+    VLNEXT = 14,
+    /// Character to flush output.
+    VFLUSH = 15,
+    /// Switch to a different shell layer.
+    VSWTCH = 16,
+    /// Print the system status line.
+    VSTATUS = 17,
+    /// Toggle the flushing of terminal output.
+    VDISCARD = 18,
+    /// Ignore the parity errors.
+    IGNPAR = 30,
+    /// Mark the parity and framing errors.
+    PARMRK = 31,
+    /// Enable checking of parity errors.
+    INPCK = 32,
+    /// Strip the eighth bit off characters.
+    ISTRIP = 33,
+    /// Map NL into CR on input.
+    INLCR = 34,
+    /// Ignore CR on input.
+    IGNCR = 35,
+    /// Map CR to NL on input.
+    ICRNL = 36,
+    /// Translate uppercase characters to lowercase.
+    IUCLC = 37,
+    /// Enable output flow control.
+    IXON = 38,
+    /// Any character will restart after stop.
+    IXANY = 39,
+    /// Enable input flow control.
+    IXOFF = 40,
+    /// Ring bell on input queue full.
+    IMAXBEL = 41,
+    /// The terminal input and output is assumed to be encoded in UTF-8 (RFC 8160).
+    IUTF8 = 42,
+    /// Enable signals INTR, QUIT, [D]SUSP.
+    ISIG = 50,
+    /// Canonicalize input lines.
+    ICANON = 51,
+    /// Enable input and output of uppercase characters by preceding their lowercase equivalents with `\`.
+    XCASE = 52,
+    /// Enable echoing.
+    ECHO = 53,
+    /// Visually erase chars.
+    ECHOE = 54,
+    /// Kill character discards current line.
+    ECHOK = 55,
+    /// Echo NL even if ECHO is off.
+    ECHONL = 56,
+    /// Don't flush after interrupt.
+    NOFLSH = 57,
+    /// Stop background jobs from output.
+    TOSTOP = 58,
+    /// Enable extensions.
+    IEXTEN = 59,
+    /// Echo control characters as ^(Char).
+    ECHOCTL = 60,
+    /// Visual erase for line kill.
+    ECHOKE = 61,
+    /// Retype pending input.
+    PENDIN = 62,
+    /// Enable output processing.
+    OPOST = 70,
+    /// Convert lowercase to uppercase.
+    OLCUC = 71,
+    /// Map NL to CR-NL.
+    ONLCR = 72,
+    /// Translate carriage return to newline (output).
+    OCRNL = 73,
+    /// Translate newline to carriage return-newline (output).
+    ONOCR = 74,
+    /// Newline performs a carriage return (output).
+    ONLRET = 75,
+    /// 7 bit mode.
+    CS7 = 90,
+    /// 8 bit mode.
+    CS8 = 91,
+    /// Parity enable.
+    PARENB = 92,
+    /// Odd parity, else even.
+    PARODD = 93,
+    /// Specifies the input baud rate in bits per second.
+    TTY_OP_ISPEED = 128,
+    /// Specifies the output baud rate in bits per second.
+    TTY_OP_OSPEED = 129,
+}
+
+/// The opcode that terminates the encoded terminal modes.
+const TTY_OP_END: u8 = 0;
+
+/// A set of terminal modes sent along with a pseudo-terminal request.
+#[derive(Debug, Clone, Default)]
+pub struct TerminalModes {
+    modes: Vec<(TerminalMode, u32)>,
+}
+
+impl TerminalModes {
+    /// Create an empty set of terminal modes.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the value of a terminal mode, replacing the previous one.
+    ///
+    /// The flag modes take `0` or `1` as the value, and the character modes
+    /// take the character code (`255` if disabled).
+    pub fn set(&mut self, mode: TerminalMode, value: u32) -> &mut Self {
+        match self.modes.iter_mut().find(|(m, _)| *m == mode) {
+            Some((_, v)) => *v = value,
+            None => self.modes.push((mode, value)),
+        }
+        self
+    }
+
+    /// Set the value of a flag mode.
+    pub fn set_flag(&mut self, mode: TerminalMode, enabled: bool) -> &mut Self {
+        self.set(mode, enabled as u32)
+    }
+
+    /// Return the value of a terminal mode, if specified.
+    pub fn get(&self, mode: TerminalMode) -> Option<u32> {
+        self.modes
+            .iter()
+            .find(|(m, _)| *m == mode)
+            .map(|(_, value)| *value)
+    }
+
+    /// Encode the terminal modes into the wire format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.modes.len() * 5 + 1);
+        for &(mode, value) in &self.modes {
+            buf.push(mode as u8);
+            buf.extend_from_slice(&value.to_be_bytes());
+        }
+        buf.push(TTY_OP_END);
+        buf
+    }
+}
+
+/// The dimensions of a pseudo-terminal.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PtySize {
+    /// The number of columns in characters.
+    pub cols: u32,
+    /// The number of rows in characters.
+    pub rows: u32,
+    /// The width in pixels, or `0` if unknown.
+    pub pixel_width: u32,
+    /// The height in pixels, or `0` if unknown.
+    pub pixel_height: u32,
+}
+
+impl Default for PtySize {
+    fn default() -> Self {
+        Self {
+            cols: 80,
+            rows: 24,
+            pixel_width: 0,
+            pixel_height: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_modes() {
+        assert_eq!(TerminalModes::new().to_bytes(), vec![TTY_OP_END]);
+    }
+
+    #[test]
+    fn encode_modes() {
+        let mut modes = TerminalModes::new();
+        modes
+            .set(TerminalMode::VINTR, 3)
+            .set_flag(TerminalMode::ECHO, false);
+        assert_eq!(
+            modes.to_bytes(),
+            vec![
+                1, 0, 0, 0, 3, //
+                53, 0, 0, 0, 0, //
+                TTY_OP_END,
+            ]
+        );
+    }
+
+    #[test]
+    fn encode_values_in_big_endian() {
+        let mut modes = TerminalModes::new();
+        modes.set(TerminalMode::TTY_OP_OSPEED, 38400);
+        assert_eq!(modes.to_bytes(), vec![129, 0, 0, 0x96, 0x00, TTY_OP_END]);
+    }
+
+    #[test]
+    fn replace_duplicated_mode() {
+        let mut modes = TerminalModes::new();
+        modes
+            .set(TerminalMode::VERASE, 8)
+            .set(TerminalMode::VERASE, 127);
+        assert_eq!(modes.get(TerminalMode::VERASE), Some(127));
+        assert_eq!(modes.to_bytes(), vec![3, 0, 0, 0, 127, TTY_OP_END]);
+    }
+}
@@ -1,24 +1,43 @@
 use crate::{
     auth::{AuthContext, Authenticator},
     channel::Channel,
-    error::{Result, Ssh2Error},
+    command::RemoteCommand,
+    error::{Error, Result, Ssh2Error},
+    ffi,
+    forward::RemoteListener,
+    instrument::instrument,
+    memory::{self, Allocator},
     sftp::Sftp,
+    shell::ShellSession,
+    stats::Stats,
 };
 use futures::{
     future::poll_fn,
-    ready,
-    task::{self, Poll},
+    task::{self, ArcWake, Poll, Waker},
 };
 use libssh2_sys as sys;
 use mio::net::TcpStream;
 use std::{
+    cell::{Cell, RefCell},
+    collections::VecDeque,
     ffi::{CStr, CString},
+    fmt, io,
+    marker::PhantomData,
+    mem,
+    net::SocketAddr,
     pin::Pin,
     ptr::{self, NonNull},
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
 use tokio::io::PollEvented;
 
 bitflags::bitflags! {
+    #[derive(Default)]
     #[repr(transparent)]
     struct BlockDirections: libc::c_int {
         const READ = sys::LIBSSH2_SESSION_BLOCK_INBOUND;
@@ -26,14 +45,100 @@ bitflags::bitflags! {
     }
 }
 
+bitflags::bitflags! {
+    /// The categories of the trace output emitted by libssh2.
+    #[repr(transparent)]
+    pub struct TraceFlags: libc::c_int {
+        /// Transport layer.
+        const TRANSPORT = sys::LIBSSH2_TRACE_TRANS;
+        /// Key exchange.
+        const KEX = sys::LIBSSH2_TRACE_KEX;
+        /// User authentication.
+        const AUTH = sys::LIBSSH2_TRACE_AUTH;
+        /// Connection layer.
+        const CONN = sys::LIBSSH2_TRACE_CONN;
+        /// SCP subsystem.
+        const SCP = sys::LIBSSH2_TRACE_SCP;
+        /// SFTP subsystem.
+        const SFTP = sys::LIBSSH2_TRACE_SFTP;
+        /// Failures.
+        const ERROR = sys::LIBSSH2_TRACE_ERROR;
+        /// Public key subsystem.
+        const PUBLICKEY = sys::LIBSSH2_TRACE_PUBLICKEY;
+        /// Low-level socket I/O.
+        const SOCKET = sys::LIBSSH2_TRACE_SOCKET;
+    }
+}
+
+/// The states of a `Session`.
+pub trait State: private::Sealed {}
+
+mod private {
+    pub trait Sealed {}
+}
+
+/// The state of a `Session` before the handshake.
+#[derive(Debug)]
+pub enum Unconnected {}
+
+/// The state of a `Session` after the handshake, but not yet authenticated.
+#[derive(Debug)]
+pub enum Connected {}
+
+/// The state of a `Session` that has been authenticated.
+#[derive(Debug)]
+pub enum Authenticated {}
+
+impl State for Unconnected {}
+impl State for Connected {}
+impl State for Authenticated {}
+impl private::Sealed for Unconnected {}
+impl private::Sealed for Connected {}
+impl private::Sealed for Authenticated {}
+
 /// A handle to an SSH session.
-pub struct Session {
+///
+/// The state type parameter tracks the lifecycle of the session, so
+/// that the operations are only available after the handshake or the
+/// authentication has been completed.
+pub struct Session<S: State = Unconnected> {
+    inner: Rc<RefCell<SessionInner>>,
+    _state: PhantomData<S>,
+}
+
+pub(crate) struct SessionInner {
     raw: NonNull<sys::LIBSSH2_SESSION>,
     stream: Option<PollEvented<TcpStream>>,
-    blocking_directions: Option<BlockDirections>,
+    waiters: Arc<Waiters>,
+    span: Box<tracing::Span>,
+    remote_addr: Option<SocketAddr>,
+    username: Option<String>,
+    next_channel_id: u64,
+    pending_kills: Vec<PendingKill>,
+    // referred by libssh2 until the session is released.
+    context: Box<SessionContext>,
+}
+
+/// The state referred by the callback functions via the abstract pointer of the session.
+pub(crate) struct SessionContext {
+    pub(crate) allocator: Option<Box<dyn Allocator>>,
+    x11_channels: RefCell<VecDeque<NonNull<sys::LIBSSH2_CHANNEL>>>,
+    auth_agent_channels: RefCell<VecDeque<NonNull<sys::LIBSSH2_CHANNEL>>>,
+    stats: Cell<Stats>,
+}
+
+impl SessionContext {
+    pub(crate) fn new(allocator: Option<Box<dyn Allocator>>) -> Self {
+        Self {
+            allocator,
+            x11_channels: RefCell::default(),
+            auth_agent_channels: RefCell::default(),
+            stats: Cell::default(),
+        }
+    }
 }
 
-impl Drop for Session {
+impl Drop for SessionInner {
     fn drop(&mut self) {
         unsafe {
             let _ = sys::libssh2_session_free(self.raw.as_ptr());
@@ -41,24 +146,66 @@ impl Drop for Session {
     }
 }
 
-impl Session {
-    /// Initialize an SSH session.
-    pub fn new() -> Result<Self> {
+impl SessionInner {
+    fn init(allocator: Option<Box<dyn Allocator>>) -> Result<Self> {
         sys::init();
 
+        let context = Box::new(SessionContext::new(allocator));
+        let abstract_ = &*context as *const SessionContext as *mut libc::c_void;
+
         unsafe {
-            let raw = NonNull::new(sys::libssh2_session_init_ex(
-                /* alloc */ None,
-                /* free */ None,
-                /* realloc */ None,
-                ptr::null_mut(),
-            ))
+            let raw = NonNull::new(match context.allocator {
+                Some(..) => sys::libssh2_session_init_ex(
+                    Some(memory::alloc_func),
+                    Some(memory::free_func),
+                    Some(memory::realloc_func),
+                    abstract_,
+                ),
+                None => sys::libssh2_session_init_ex(
+                    /* alloc */ None, /* free */ None, /* realloc */ None, abstract_,
+                ),
+            })
             .ok_or_else(|| Ssh2Error::new(0, "failed to initialize SSH session"))?;
 
+            let x11_open: ffi::LIBSSH2_X11_OPEN_FUNC = x11_open;
+            ffi::libssh2_session_callback_set(
+                raw.as_ptr(),
+                ffi::LIBSSH2_CALLBACK_X11,
+                x11_open as *mut libc::c_void,
+            );
+            let auth_agent_open: ffi::LIBSSH2_AUTHAGENT_FUNC = auth_agent_open;
+            ffi::libssh2_session_callback_set(
+                raw.as_ptr(),
+                ffi::LIBSSH2_CALLBACK_AUTHAGENT,
+                auth_agent_open as *mut libc::c_void,
+            );
+            let send: ffi::LIBSSH2_SEND_FUNC = send;
+            ffi::libssh2_session_callback_set(
+                raw.as_ptr(),
+                ffi::LIBSSH2_CALLBACK_SEND,
+                send as *mut libc::c_void,
+            );
+            let recv: ffi::LIBSSH2_RECV_FUNC = recv;
+            ffi::libssh2_session_callback_set(
+                raw.as_ptr(),
+                ffi::LIBSSH2_CALLBACK_RECV,
+                recv as *mut libc::c_void,
+            );
+
             Ok(Self {
                 raw,
                 stream: None,
-                blocking_directions: None,
+                waiters: Arc::default(),
+                span: Box::new(tracing::debug_span!(
+                    "session",
+                    remote_addr = tracing::field::Empty,
+                    username = tracing::field::Empty,
+                )),
+                remote_addr: None,
+                username: None,
+                next_channel_id: 0,
+                pending_kills: Vec::new(),
+                context,
             })
         }
     }
@@ -67,6 +214,10 @@ impl Session {
         self.raw.as_mut()
     }
 
+    pub(crate) fn as_ptr(&self) -> *mut sys::LIBSSH2_SESSION {
+        self.raw.as_ptr()
+    }
+
     pub(crate) fn last_error(&mut self) -> Ssh2Error {
         unsafe { Ssh2Error::last_error(self.raw.as_mut()) } //
             .unwrap_or_else(Ssh2Error::unknown)
@@ -80,12 +231,128 @@ impl Session {
         }
     }
 
-    fn stream_mut(&mut self) -> &mut PollEvented<TcpStream> {
-        self.stream.as_mut().unwrap()
+    pub(crate) fn stats(&self) -> Stats {
+        self.context.stats.get()
+    }
+
+    pub(crate) fn next_channel_id(&mut self) -> u64 {
+        let id = self.next_channel_id;
+        self.next_channel_id += 1;
+        id
+    }
+
+    /// Queue the signal and the close of a dropped channel, which are sent
+    /// without blocking whenever the session is polled.
+    pub(crate) fn kill_on_drop(&mut self, raw: NonNull<sys::LIBSSH2_CHANNEL>, signal: String) {
+        self.pending_kills.push(PendingKill {
+            raw,
+            signal,
+            state: KillState::Signal,
+        });
+        self.flush_pending_kills();
+    }
+
+    /// Make progress on the queued kills, and free the channels once they
+    /// are closed.
+    fn flush_pending_kills(&mut self) {
+        self.pending_kills.retain_mut(|kill| loop {
+            let channel = kill.raw.as_ptr();
+            let rc = unsafe {
+                match kill.state {
+                    KillState::Signal => ffi::libssh2_channel_signal_ex(
+                        channel,
+                        kill.signal.as_ptr() as *const libc::c_char,
+                        kill.signal.len(),
+                    ),
+                    KillState::Close => sys::libssh2_channel_close(channel),
+                    KillState::Free => sys::libssh2_channel_free(channel),
+                }
+            };
+            if rc == sys::LIBSSH2_ERROR_EAGAIN {
+                return true;
+            }
+            if rc != 0 {
+                tracing::debug!(rc, "failed to kill the dropped channel: {:?}", kill.state);
+            }
+            kill.state = match kill.state {
+                KillState::Signal => KillState::Close,
+                KillState::Close => KillState::Free,
+                KillState::Free => return false,
+            };
+        });
+    }
+
+    /// Take an X11 channel opened by the remote host, if any.
+    pub(crate) fn take_x11_channel(&self) -> Option<NonNull<sys::LIBSSH2_CHANNEL>> {
+        self.context.x11_channels.borrow_mut().pop_front()
+    }
+
+    /// Take an agent channel opened by the remote host, if any.
+    pub(crate) fn take_auth_agent_channel(&self) -> Option<NonNull<sys::LIBSSH2_CHANNEL>> {
+        self.context.auth_agent_channels.borrow_mut().pop_front()
+    }
+
+    /// Record the connection metadata of this session into the span.
+    pub(crate) fn record_metadata(&self, span: &tracing::Span) {
+        if let Some(remote_addr) = self.remote_addr {
+            span.record("remote_addr", tracing::field::display(remote_addr));
+        }
+        if let Some(ref username) = self.username {
+            span.record("username", username.as_str());
+        }
+    }
+
+    fn stream_mut(&mut self) -> io::Result<&mut PollEvented<TcpStream>> {
+        self.stream.as_mut().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotConnected, "the session is not connected")
+        })
+    }
+
+    /// Start accounting the time the operation waits for the socket.
+    fn start_blocking(&self, timer: &BlockTimer, directions: BlockDirections) {
+        match timer.blocked.get() {
+            Some((_, blocked)) if blocked == directions => return,
+            Some(..) => self.stop_blocking(timer),
+            None => (),
+        }
+        timer.blocked.set(Some((Instant::now(), directions)));
     }
 
-    #[allow(clippy::cognitive_complexity)]
-    pub(crate) fn poll_with<F, R>(&mut self, cx: &mut task::Context<'_>, f: F) -> Poll<Result<R>>
+    /// Account the time the operation has waited for the socket.
+    fn stop_blocking(&self, timer: &BlockTimer) {
+        if let Some((since, directions)) = timer.blocked.take() {
+            let elapsed = since.elapsed();
+            let read = if directions.contains(BlockDirections::READ) {
+                elapsed
+            } else {
+                Duration::default()
+            };
+            let write = if directions.contains(BlockDirections::WRITE) {
+                elapsed
+            } else {
+                Duration::default()
+            };
+
+            let mut stats = self.context.stats.get();
+            stats.record_blocked(read, write);
+            self.context.stats.set(stats);
+
+            let (total_read, total_write) = timer.elapsed.get();
+            timer.elapsed.set((total_read + read, total_write + write));
+        }
+    }
+
+    /// Call the libssh2 function, and register the waker if it would block.
+    ///
+    /// The timer is owned by the polling operation, so that the time spent
+    /// waiting is accounted per operation even if multiple operations wait
+    /// on the session concurrently.
+    pub(crate) fn poll_with<F, R>(
+        &mut self,
+        cx: &mut task::Context<'_>,
+        timer: &BlockTimer,
+        f: F,
+    ) -> Poll<Result<R>>
     where
         F: FnOnce(&mut Self) -> std::result::Result<R, Ssh2Error>,
     {
@@ -95,44 +362,172 @@ impl Session {
             mask
         }
 
-        let mut read_ready = false;
-        let mut write_ready = false;
-        if let Some(directions) = self.blocking_directions {
-            if directions.contains(BlockDirections::READ) {
-                tracing::trace!("poll read readiness");
-                ready!(self.stream_mut().poll_read_ready(cx, read_mask()))?;
-                read_ready = true;
-            }
-            if directions.contains(BlockDirections::WRITE) {
-                tracing::trace!("poll write readiness");
-                ready!(self.stream_mut().poll_write_ready(cx))?;
-                write_ready = true;
-            }
+        if !self.pending_kills.is_empty() {
+            self.flush_pending_kills();
         }
-        self.blocking_directions.take();
-
-        match f(&mut *self) {
-            Ok(ret) => Poll::Ready(Ok(ret)),
-            Err(ref err) if err.code() == sys::LIBSSH2_ERROR_EAGAIN => {
-                let directions =
-                    unsafe { sys::libssh2_session_block_directions(self.raw.as_mut()) };
-                self.blocking_directions = Some(BlockDirections::from_bits_truncate(directions));
-                tracing::trace!("blocking_directions={:?}", self.blocking_directions);
-
-                let stream = self.stream_mut();
-                if read_ready {
-                    tracing::trace!("clear read readiness");
-                    stream.clear_read_ready(cx, read_mask())?;
-                }
-                if write_ready {
-                    tracing::trace!("clear write readiness");
-                    stream.clear_write_ready(cx)?;
-                }
 
-                Poll::Pending
+        let notified = self.waiters.notified.swap(false, Ordering::SeqCst);
+        let res = f(&mut *self);
+        if notified {
+            // The socket may have been read by this call, so the other tasks
+            // need to check whether their packets have arrived.
+            self.waiters.wake_all();
+        }
+
+        match res {
+            Err(ref err) if err.code() == sys::LIBSSH2_ERROR_EAGAIN => (),
+            res => {
+                self.stop_blocking(timer);
+                return Poll::Ready(res.map_err(Into::into));
             }
-            Err(err) => Poll::Ready(Err(err.into())),
         }
+
+        let directions = BlockDirections::from_bits_truncate(unsafe {
+            sys::libssh2_session_block_directions(self.raw.as_mut())
+        });
+        tracing::trace!("blocking_directions={:?}", directions);
+        self.start_blocking(timer, directions);
+
+        self.waiters.register(cx.waker());
+        let waiters = self.waiters.clone();
+        let waker = task::waker_ref(&waiters);
+        let mut waiters_cx = task::Context::from_waker(&waker);
+
+        let stream = self.stream_mut()?;
+        if directions.contains(BlockDirections::READ) {
+            tracing::trace!("clear read readiness");
+            stream.clear_read_ready(&mut waiters_cx, read_mask())?;
+        }
+        if directions.contains(BlockDirections::WRITE) {
+            tracing::trace!("clear write readiness");
+            stream.clear_write_ready(&mut waiters_cx)?;
+        }
+        if directions.is_empty() {
+            cx.waker().wake_by_ref();
+        }
+
+        Poll::Pending
+    }
+}
+
+/// A channel dropped with the kill-on-drop signal, which is signalled,
+/// closed and freed in turn.
+struct PendingKill {
+    raw: NonNull<sys::LIBSSH2_CHANNEL>,
+    signal: String,
+    state: KillState,
+}
+
+#[derive(Debug, Copy, Clone)]
+enum KillState {
+    Signal,
+    Close,
+    Free,
+}
+
+/// The time spent by an operation waiting for the socket.
+#[derive(Debug, Default)]
+pub(crate) struct BlockTimer {
+    blocked: Cell<Option<(Instant, BlockDirections)>>,
+    elapsed: Cell<(Duration, Duration)>,
+}
+
+impl BlockTimer {
+    /// Take the time spent waiting for the socket to become readable and
+    /// writable respectively, since the last call.
+    pub(crate) fn take_elapsed(&self) -> (Duration, Duration) {
+        self.elapsed.take()
+    }
+}
+
+/// The set of tasks waiting for the readiness of the socket.
+///
+/// The channels of a session share the socket, and libssh2 buffers the
+/// packets for the other channels while reading the socket on behalf of
+/// one of them. Hence the readiness of the socket is notified to all
+/// waiting tasks, rather than the last task that has polled it.
+#[derive(Default)]
+struct Waiters {
+    wakers: Mutex<Vec<Waker>>,
+    notified: AtomicBool,
+}
+
+impl Waiters {
+    fn register(&self, waker: &Waker) {
+        let mut wakers = self.wakers.lock().unwrap();
+        if !wakers.iter().any(|w| w.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+    }
+
+    fn wake_all(&self) {
+        let wakers = mem::take(&mut *self.wakers.lock().unwrap());
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+impl ArcWake for Waiters {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.notified.store(true, Ordering::SeqCst);
+        arc_self.wake_all();
+    }
+}
+
+impl<S: State> Session<S> {
+    fn transit<T: State>(self) -> Session<T> {
+        Session {
+            inner: self.inner,
+            _state: PhantomData,
+        }
+    }
+
+    /// Enable the trace output of libssh2 for the specified categories.
+    ///
+    /// The trace messages are forwarded to `tracing` as `TRACE` level events
+    /// with the target `libssh2`, within the span associated with this session.
+    /// Note that libssh2 emits them only when it is built with debugging enabled.
+    pub fn trace(&mut self, flags: TraceFlags) -> Result<()> {
+        let mut inner = self.inner.borrow_mut();
+        let raw = inner.raw.as_ptr();
+        let context = &*inner.span as *const tracing::Span as *mut libc::c_void;
+        inner.rc(unsafe { ffi::libssh2_trace_sethandler(raw, context, Some(trace_handler)) })?;
+        inner.rc(unsafe { sys::libssh2_trace(raw, flags.bits()) })?;
+        Ok(())
+    }
+
+    /// Return a snapshot of the traffic statistics of this session.
+    ///
+    /// The bytes are counted on the socket, so they include the traffic
+    /// of the handshake and the authentication as well as that of all
+    /// channels and SFTP handles opened on this session.
+    pub fn stats(&self) -> Stats {
+        self.inner.borrow().stats()
+    }
+
+    /// Return whether the session has been successfully authenticated or not.
+    pub fn authenticated(&self) -> bool {
+        unsafe { sys::libssh2_userauth_authenticated(self.inner.borrow().as_ptr()) != 0 }
+    }
+}
+
+impl Session<Unconnected> {
+    /// Initialize an SSH session.
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            inner: Rc::new(RefCell::new(SessionInner::init(None)?)),
+            _state: PhantomData,
+        })
+    }
+
+    /// Initialize an SSH session whose heap memory is managed by the specified allocator.
+    pub fn with_allocator(allocator: impl Allocator) -> Result<Self> {
+        let allocator = Box::new(allocator) as Box<dyn Allocator>;
+        Ok(Self {
+            inner: Rc::new(RefCell::new(SessionInner::init(Some(allocator))?)),
+            _state: PhantomData,
+        })
     }
 
     /// Set the banner that will be sent to the remote host when the SSH session is started.
@@ -140,13 +535,14 @@ impl Session {
     /// By default, a banner corresponding to the protocol and libssh2 version will be sent.
     pub fn set_banner(&mut self, banner: impl AsRef<str>) -> Result<()> {
         let banner = CString::new(banner.as_ref())?;
-        let raw = self.raw.as_ptr();
-        self.rc(unsafe { sys::libssh2_session_banner_set(raw, banner.as_ptr()) })?;
+        let mut inner = self.inner.borrow_mut();
+        let raw = inner.as_ptr();
+        inner.rc(unsafe { sys::libssh2_session_banner_set(raw, banner.as_ptr()) })?;
         Ok(())
     }
 
     /// Start the transport layer protocol negotiation with the connected host.
-    pub async fn handshake(&mut self, stream: std::net::TcpStream) -> Result<()> {
+    pub async fn handshake(self, stream: std::net::TcpStream) -> Result<Session<Connected>> {
         #[cfg(unix)]
         fn get_socket_fd(stream: &std::net::TcpStream) -> std::os::unix::io::RawFd {
             use std::os::unix::prelude::*;
@@ -161,117 +557,450 @@ impl Session {
 
         let fd = get_socket_fd(&stream);
 
-        let stream = PollEvented::new(TcpStream::from_stream(stream)?)?;
-        self.stream.replace(stream);
+        {
+            let mut inner = self.inner.borrow_mut();
+            inner.remote_addr = stream.peer_addr().ok();
+            inner.record_metadata(&inner.span);
 
-        poll_fn(|cx| {
-            let raw = self.raw.as_ptr();
-            self.poll_with(cx, |sess| {
-                sess.rc(unsafe { sys::libssh2_session_handshake(raw, fd) })
-                    .map(drop)
-            })
-        })
-        .await
+            let stream = PollEvented::new(TcpStream::from_stream(stream)?)?;
+            inner.stream.replace(stream);
+        }
+
+        let span = op_span!(self.inner.borrow(), "handshake");
+        let timer = BlockTimer::default();
+        instrument(
+            span,
+            poll_fn(|cx| {
+                let mut inner = self.inner.borrow_mut();
+                let raw = inner.as_ptr();
+                inner.poll_with(cx, &timer, |sess| {
+                    sess.rc(unsafe { sys::libssh2_session_handshake(raw, fd) })
+                        .map(drop)
+                })
+            }),
+        )
+        .await?;
+
+        Ok(self.transit())
+    }
+}
+
+impl Session<Connected> {
+    /// List the supported authentication methods for an user.
+    pub async fn list_userauth(&mut self, username: &str) -> Result<Vec<u8>> {
+        let span = op_span!(self.inner.borrow(), "list_userauth", user = username);
+        let timer = BlockTimer::default();
+        let list = instrument(
+            span,
+            poll_fn(|cx| {
+                self.inner.borrow_mut().poll_with(cx, &timer, |sess| {
+                    let list = NonNull::new(unsafe {
+                        sys::libssh2_userauth_list(
+                            sess.raw.as_mut(),
+                            username.as_ptr() as *const libc::c_char,
+                            username.len() as libc::c_uint,
+                        )
+                    })
+                    .ok_or_else(|| sess.last_error())?;
+                    Ok(unsafe { CStr::from_ptr(list.as_ptr()).to_owned() })
+                })
+            }),
+        )
+        .await?;
+        Ok(list.into_bytes())
     }
 
     /// Attempt the specified authentication.
-    pub async fn authenticate<'a, A>(&'a mut self, username: &'a str, auth: A) -> Result<()>
+    ///
+    /// On failure, the session is given back within the error so that
+    /// another authentication method can be tried.
+    pub async fn authenticate<A>(
+        self,
+        username: &str,
+        auth: A,
+    ) -> std::result::Result<Session<Authenticated>, AuthError>
     where
         A: Authenticator + Unpin,
     {
+        {
+            let mut inner = self.inner.borrow_mut();
+            inner.username = Some(username.to_owned());
+            inner.record_metadata(&inner.span);
+        }
+
+        let span = op_span!(self.inner.borrow(), "authenticate");
+        let timer = BlockTimer::default();
         let mut auth = auth;
-        poll_fn(|cx| {
-            Pin::new(&mut auth).poll_authenticate(
-                cx,
-                &mut AuthContext {
-                    sess: self,
-                    username: &*username,
-                },
-            )
-        })
-        .await
+        let res = instrument(
+            span,
+            poll_fn(|cx| {
+                Pin::new(&mut auth).poll_authenticate(
+                    cx,
+                    &mut AuthContext {
+                        sess: &mut self.inner.borrow_mut(),
+                        username,
+                        timer: &timer,
+                    },
+                )
+            }),
+        )
+        .await;
+
+        match res {
+            Ok(()) => Ok(self.transit()),
+            Err(error) => Err(AuthError {
+                session: self,
+                error,
+            }),
+        }
     }
+}
 
-    /// Return whether the session has been successfully authenticated or not.
-    pub fn authenticated(&self) -> bool {
-        unsafe { sys::libssh2_userauth_authenticated(self.raw.as_ptr()) != 0 }
+impl Session<Authenticated> {
+    pub async fn open_channel(
+        &self,
+        channel_type: &str,
+        window_size: Option<u32>,
+        packet_size: Option<u32>,
+        msg: Option<&str>,
+    ) -> Result<Channel> {
+        let id = self.inner.borrow_mut().next_channel_id();
+
+        let span = op_span!(
+            self.inner.borrow(),
+            "open_channel",
+            channel_id = id,
+            channel_type
+        );
+        let timer = BlockTimer::default();
+        let raw = instrument(
+            span,
+            poll_fn(|cx| {
+                self.inner.borrow_mut().poll_with(cx, &timer, |sess| {
+                    let window_size = window_size.unwrap_or(sys::LIBSSH2_CHANNEL_WINDOW_DEFAULT);
+                    let packet_size = packet_size.unwrap_or(sys::LIBSSH2_CHANNEL_PACKET_DEFAULT);
+                    let (msg, msg_len) = match msg {
+                        Some(msg) => (
+                            msg.as_ptr() as *const libc::c_char,
+                            msg.len() as libc::c_uint,
+                        ),
+                        None => (ptr::null(), 0),
+                    };
+
+                    let raw = NonNull::new(unsafe {
+                        sys::libssh2_channel_open_ex(
+                            sess.raw.as_mut(),
+                            channel_type.as_ptr() as *const libc::c_char,
+                            channel_type.len() as libc::c_uint,
+                            window_size,
+                            packet_size,
+                            msg,
+                            msg_len,
+                        )
+                    });
+                    raw.ok_or_else(|| sess.last_error())
+                })
+            }),
+        )
+        .await?;
+
+        Ok(Channel::new(raw, id, self.inner.clone()))
     }
 
-    /// List the supported authentication methods for an user.
-    pub async fn list_userauth(&mut self, username: &str) -> Result<Vec<u8>> {
-        let list = poll_fn(|cx| {
-            self.poll_with(cx, |sess| {
-                let list = NonNull::new(unsafe {
-                    sys::libssh2_userauth_list(
-                        sess.raw.as_mut(),
-                        username.as_ptr() as *const libc::c_char,
-                        username.len() as libc::c_uint,
-                    )
+    pub async fn open_channel_session(&self) -> Result<Channel> {
+        self.open_channel("session", None, None, None).await
+    }
+
+    /// Create a builder of the command executed on the remote host.
+    pub fn command(&self, program: &str) -> RemoteCommand<'_> {
+        RemoteCommand::new(self, program)
+    }
+
+    /// Open a shell which keeps its state across the commands run in it.
+    pub async fn open_shell(&self) -> Result<ShellSession> {
+        let channel = self.open_channel_session().await?;
+        ShellSession::new(channel).await
+    }
+
+    /// Open a channel connected to the specified host and port via the remote host.
+    ///
+    /// `src` is the originator of the connection reported to the remote
+    /// host, which defaults to `127.0.0.1:22`.
+    pub async fn channel_direct_tcpip(
+        &self,
+        host: &str,
+        port: u16,
+        src: Option<SocketAddr>,
+    ) -> Result<Channel> {
+        let id = self.inner.borrow_mut().next_channel_id();
+        let src = src.unwrap_or_else(|| SocketAddr::from(([127, 0, 0, 1], 22)));
+
+        let span = op_span!(
+            self.inner.borrow(),
+            "channel_direct_tcpip",
+            channel_id = id,
+            host,
+            port,
+            src = %src
+        );
+        let c_host = CString::new(host)?;
+        let c_shost = CString::new(src.ip().to_string())?;
+        let timer = BlockTimer::default();
+        let raw = instrument(
+            span,
+            poll_fn(|cx| {
+                self.inner.borrow_mut().poll_with(cx, &timer, |sess| {
+                    let raw = NonNull::new(unsafe {
+                        sys::libssh2_channel_direct_tcpip_ex(
+                            sess.raw.as_mut(),
+                            c_host.as_ptr(),
+                            port as libc::c_int,
+                            c_shost.as_ptr(),
+                            src.port() as libc::c_int,
+                        )
+                    });
+                    raw.ok_or_else(|| sess.last_error())
                 })
-                .ok_or_else(|| sess.last_error())?;
-                Ok(unsafe { CStr::from_ptr(list.as_ptr()).to_owned() })
-            })
-        })
+            }),
+        )
         .await?;
-        Ok(list.into_bytes())
+
+        Ok(Channel::new(raw, id, self.inner.clone()))
     }
 
-    pub async fn open_channel<'a>(
-        &'a mut self,
-        channel_type: &'a str,
-        window_size: Option<u32>,
-        packet_size: Option<u32>,
-        msg: Option<&'a str>,
-    ) -> Result<Channel<'a>> {
-        let raw = poll_fn(|cx| {
-            self.poll_with(cx, |sess| {
-                let window_size = window_size.unwrap_or(sys::LIBSSH2_CHANNEL_WINDOW_DEFAULT);
-                let packet_size = packet_size.unwrap_or(sys::LIBSSH2_CHANNEL_PACKET_DEFAULT);
-                let (msg, msg_len) = match msg {
-                    Some(msg) => (
-                        msg.as_ptr() as *const libc::c_char,
-                        msg.len() as libc::c_uint,
-                    ),
-                    None => (ptr::null(), 0),
-                };
-
-                let raw = NonNull::new(unsafe {
-                    sys::libssh2_channel_open_ex(
-                        sess.raw.as_mut(),
-                        channel_type.as_ptr() as *const libc::c_char,
-                        channel_type.len() as libc::c_uint,
-                        window_size,
-                        packet_size,
-                        msg,
-                        msg_len,
-                    )
-                });
-                raw.ok_or_else(|| sess.last_error())
-            })
-        })
+    /// Open a channel connected to the Unix domain socket on the remote host.
+    ///
+    /// This uses the `direct-streamlocal@openssh.com` extension of OpenSSH.
+    pub async fn channel_direct_streamlocal(&self, socket_path: &str) -> Result<Channel> {
+        let id = self.inner.borrow_mut().next_channel_id();
+
+        let span = op_span!(
+            self.inner.borrow(),
+            "channel_direct_streamlocal",
+            channel_id = id,
+            socket_path
+        );
+        let c_socket_path = CString::new(socket_path)?;
+        let c_shost = CString::new("127.0.0.1")?;
+        let timer = BlockTimer::default();
+        let raw = instrument(
+            span,
+            poll_fn(|cx| {
+                self.inner.borrow_mut().poll_with(cx, &timer, |sess| {
+                    let raw = NonNull::new(unsafe {
+                        sys::libssh2_channel_direct_streamlocal_ex(
+                            sess.raw.as_mut(),
+                            c_socket_path.as_ptr(),
+                            c_shost.as_ptr(),
+                            22,
+                        )
+                    });
+                    raw.ok_or_else(|| sess.last_error())
+                })
+            }),
+        )
         .await?;
 
-        Ok(Channel::new(raw, self))
+        Ok(Channel::new(raw, id, self.inner.clone()))
     }
 
-    #[allow(clippy::needless_lifetimes)]
-    pub async fn open_channel_session<'sess>(&'sess mut self) -> Result<Channel<'sess>> {
-        self.open_channel("session", None, None, None).await
+    /// Request the remote host to listen on the specified address, as with `ssh -R`.
+    ///
+    /// If `host` is omitted, the remote host listens on all addresses. If
+    /// `port` is `0`, the remote host chooses a port, which is reported by
+    /// `RemoteListener::port`.
+    pub async fn forward_listen(
+        &self,
+        host: Option<&str>,
+        port: u16,
+        queue_maxsize: Option<u32>,
+    ) -> Result<RemoteListener> {
+        let span = op_span!(self.inner.borrow(), "forward_listen", host, port);
+        let c_host = host.map(CString::new).transpose()?;
+        let timer = BlockTimer::default();
+        let (raw, bound_port) = instrument(
+            span,
+            poll_fn(|cx| {
+                self.inner.borrow_mut().poll_with(cx, &timer, |sess| {
+                    let mut bound_port = port as libc::c_int;
+                    let raw = NonNull::new(unsafe {
+                        sys::libssh2_channel_forward_listen_ex(
+                            sess.raw.as_mut(),
+                            c_host.as_ref().map_or(ptr::null(), |host| host.as_ptr()),
+                            port as libc::c_int,
+                            &mut bound_port,
+                            queue_maxsize.unwrap_or(16) as libc::c_int,
+                        )
+                    })
+                    .ok_or_else(|| sess.last_error())?;
+                    Ok((raw, bound_port as u16))
+                })
+            }),
+        )
+        .await?;
+
+        Ok(RemoteListener::new(raw, bound_port, self.inner.clone()))
     }
 
-    #[allow(clippy::needless_lifetimes)]
-    pub async fn sftp<'sess>(&'sess mut self) -> Result<Sftp<'sess>> {
-        let raw = poll_fn(|cx| {
-            self.poll_with(cx, |sess| {
-                NonNull::new(unsafe { sys::libssh2_sftp_init(sess.raw.as_mut()) }) //
-                    .ok_or_else(|| sess.last_error())
-            })
-        })
+    pub async fn sftp(&self) -> Result<Sftp> {
+        let span = op_span!(self.inner.borrow(), "sftp");
+        let timer = BlockTimer::default();
+        let raw = instrument(
+            span,
+            poll_fn(|cx| {
+                self.inner.borrow_mut().poll_with(cx, &timer, |sess| {
+                    NonNull::new(unsafe { sys::libssh2_sftp_init(sess.raw.as_mut()) }) //
+                        .ok_or_else(|| sess.last_error())
+                })
+            }),
+        )
         .await?;
-        Ok(Sftp::new(raw, self))
+        Ok(Sftp::new(raw, self.inner.clone()))
     }
 }
 
+/// The error returned from `Session::authenticate`.
+///
+/// It holds the session, so that another authentication method can be
+/// attempted on the same connection.
+pub struct AuthError {
+    session: Session<Connected>,
+    error: Error,
+}
+
+impl fmt::Debug for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthError")
+            .field("error", &self.error)
+            .finish()
+    }
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "authentication failed: {}", self.error)
+    }
+}
+
+impl std::error::Error for AuthError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl AuthError {
+    /// Return the reason of the failure.
+    pub fn error(&self) -> &Error {
+        &self.error
+    }
+
+    /// Take the session back to retry the authentication.
+    pub fn into_session(self) -> Session<Connected> {
+        self.session
+    }
+
+    /// Consume itself and return the reason of the failure.
+    pub fn into_error(self) -> Error {
+        self.error
+    }
+}
+
+impl From<AuthError> for Error {
+    fn from(err: AuthError) -> Self {
+        err.error
+    }
+}
+
+/// Queue the X11 channel opened by the remote host, until it is taken by `X11Channels`.
+extern "C" fn x11_open(
+    _session: *mut sys::LIBSSH2_SESSION,
+    channel: *mut sys::LIBSSH2_CHANNEL,
+    shost: *const libc::c_char,
+    sport: libc::c_int,
+    abstract_: *mut *mut libc::c_void,
+) {
+    let context = unsafe { &*(*abstract_ as *const SessionContext) };
+    let shost = unsafe { CStr::from_ptr(shost) }.to_string_lossy();
+    tracing::debug!("X11 connection received from {}:{}", shost, sport);
+    if let Some(channel) = NonNull::new(channel) {
+        context.x11_channels.borrow_mut().push_back(channel);
+    }
+}
+
+/// Queue the agent channel opened by the remote host, until it is taken by
+/// `AuthAgentChannels`.
+extern "C" fn auth_agent_open(
+    _session: *mut sys::LIBSSH2_SESSION,
+    channel: *mut sys::LIBSSH2_CHANNEL,
+    abstract_: *mut *mut libc::c_void,
+) {
+    let context = unsafe { &*(*abstract_ as *const SessionContext) };
+    tracing::debug!("agent connection received");
+    if let Some(channel) = NonNull::new(channel) {
+        context.auth_agent_channels.borrow_mut().push_back(channel);
+    }
+}
+
+/// Send the data to the socket, counting the bytes into the session statistics.
+extern "C" fn send(
+    socket: libc::c_int,
+    buffer: *const libc::c_void,
+    length: libc::size_t,
+    flags: libc::c_int,
+    abstract_: *mut *mut libc::c_void,
+) -> libc::ssize_t {
+    let context = unsafe { &*(*abstract_ as *const SessionContext) };
+    let rc = unsafe { libc::send(socket, buffer, length, flags) };
+    if rc < 0 {
+        return socket_error();
+    }
+    let mut stats = context.stats.get();
+    stats.record_sent(rc as usize);
+    context.stats.set(stats);
+    rc
+}
+
+/// Receive the data from the socket, counting the bytes into the session statistics.
+extern "C" fn recv(
+    socket: libc::c_int,
+    buffer: *mut libc::c_void,
+    length: libc::size_t,
+    flags: libc::c_int,
+    abstract_: *mut *mut libc::c_void,
+) -> libc::ssize_t {
+    let context = unsafe { &*(*abstract_ as *const SessionContext) };
+    let rc = unsafe { libc::recv(socket, buffer, length, flags) };
+    if rc < 0 {
+        return socket_error();
+    }
+    let mut stats = context.stats.get();
+    stats.record_received(rc as usize);
+    context.stats.set(stats);
+    rc
+}
+
+/// Return the last socket error as `-errno`, in the same manner as the
+/// default `send`/`recv` of libssh2.
+fn socket_error() -> libc::ssize_t {
+    match io::Error::last_os_error().raw_os_error() {
+        Some(libc::EINTR) | Some(libc::EWOULDBLOCK) => -(libc::EAGAIN as libc::ssize_t),
+        Some(errno) => -(errno as libc::ssize_t),
+        None => -(libc::EIO as libc::ssize_t),
+    }
+}
+
+extern "C" fn trace_handler(
+    _: *mut sys::LIBSSH2_SESSION,
+    context: *mut libc::c_void,
+    data: *const libc::c_char,
+    length: libc::size_t,
+) {
+    let span = unsafe { &*(context as *const tracing::Span) };
+    let msg = unsafe { std::slice::from_raw_parts(data as *const u8, length) };
+    let msg = String::from_utf8_lossy(msg);
+    tracing::trace!(target: "libssh2", parent: span, "{}", msg.trim_end());
+}
+
 pub(crate) trait ReturnCode {
     fn is_success(&self) -> bool;
 }
@@ -2,7 +2,8 @@
 
 use crate::{
     error::{Result, Ssh2Error},
-    session::Session,
+    instrument::instrument,
+    session::{BlockTimer, SessionInner},
     util::{bytes_to_path, path_to_bytes},
 };
 use futures::{
@@ -11,15 +12,29 @@ use futures::{
 };
 use libssh2_sys as sys;
 use std::{
+    cell::RefCell,
     fmt, //
     io,
     mem,
     path::{Path, PathBuf},
     pin::Pin,
     ptr::{self, NonNull},
+    rc::Rc,
 };
 use tokio::io::{AsyncRead, AsyncWrite};
 
+/// Create a span for an operation on the handle.
+macro_rules! handle_span {
+    ($handle:expr, $name:literal) => {
+        op_span!(
+            $handle.sftp.sess.borrow(),
+            $name,
+            path = %$handle.path.display(),
+            handle = ?$handle.raw
+        )
+    };
+}
+
 /// The metadata about a remote file.
 ///
 /// This type is ABI-compatible with `LIBSSH2_SFTP_ATTRIBUTES`.
@@ -86,6 +101,7 @@ impl FileAttr {
 }
 
 #[derive(Debug)]
+#[allow(dead_code)] // TODO: expose the fields
 pub struct DirEntry {
     path: PathBuf,
     attr: FileAttr,
@@ -146,35 +162,44 @@ impl OpenOptions {
         self
     }
 
-    pub async fn open<'a, 'sess, P>(
-        &self,
-        path: P,
-        sftp: &'a mut Sftp<'sess>,
-    ) -> Result<File<'a, 'sess>>
+    pub async fn open<'a, P>(&self, path: P, sftp: &'a mut Sftp) -> Result<File<'a>>
     where
         P: AsRef<Path>,
     {
-        let path = path_to_bytes(path.as_ref())?;
-        let raw = poll_fn(|cx| {
-            sftp.poll_open(
-                cx, //
-                &*path,
-                self,
-                sys::LIBSSH2_SFTP_OPENFILE,
-            )
-        })
+        let path = path.as_ref();
+        let span = op_span!(sftp.sess.borrow(), "open", path = %path.display());
+        let path_bytes = path_to_bytes(path)?;
+        let timer = BlockTimer::default();
+        let raw = instrument(
+            span,
+            poll_fn(|cx| {
+                sftp.poll_open(
+                    cx, //
+                    &timer,
+                    &path_bytes,
+                    self,
+                    sys::LIBSSH2_SFTP_OPENFILE,
+                )
+            }),
+        )
         .await?;
-        Ok(File(Handle { raw, sftp }))
+        Ok(File(Handle {
+            raw,
+            path: path.to_owned(),
+            sftp,
+            read_timer: BlockTimer::default(),
+            write_timer: BlockTimer::default(),
+        }))
     }
 }
 
 /// A handle to a remote filesystem over SFTP.
-pub struct Sftp<'sess> {
+pub struct Sftp {
     raw: NonNull<sys::LIBSSH2_SFTP>,
-    sess: &'sess mut Session,
+    sess: Rc<RefCell<SessionInner>>,
 }
 
-impl Drop for Sftp<'_> {
+impl Drop for Sftp {
     fn drop(&mut self) {
         unsafe {
             // FIXME: should we handle EAGAIN at here?
@@ -183,21 +208,22 @@ impl Drop for Sftp<'_> {
     }
 }
 
-impl<'sess> Sftp<'sess> {
-    pub(crate) fn new(raw: NonNull<sys::LIBSSH2_SFTP>, sess: &'sess mut Session) -> Self {
+impl Sftp {
+    pub(crate) fn new(raw: NonNull<sys::LIBSSH2_SFTP>, sess: Rc<RefCell<SessionInner>>) -> Self {
         Self { raw, sess }
     }
 
     fn poll_stat(
         &mut self,
         cx: &mut task::Context<'_>,
+        timer: &BlockTimer,
         path: &[u8],
         stat_type: libc::c_int,
         attrs: *mut sys::LIBSSH2_SFTP_ATTRIBUTES,
     ) -> Poll<Result<()>> {
         unsafe {
             let sftp = self.raw.as_mut();
-            self.sess.poll_with(cx, |_| {
+            self.sess.borrow_mut().poll_with(cx, timer, |_| {
                 let rc = sys::libssh2_sftp_stat_ex(
                     sftp,
                     path.as_ptr() as *const libc::c_char,
@@ -218,17 +244,23 @@ impl<'sess> Sftp<'sess> {
 
     /// Acquire the metadata for a file.
     pub async fn stat(&mut self, path: impl AsRef<Path>) -> Result<FileAttr> {
+        let span = op_span!(self.sess.borrow(), "stat", path = %path.as_ref().display());
         let path = path_to_bytes(path.as_ref())?;
         unsafe {
             let mut stbuf = mem::MaybeUninit::zeroed();
-            poll_fn(|cx| {
-                self.poll_stat(
-                    cx, //
-                    &*path,
-                    sys::LIBSSH2_SFTP_STAT,
-                    stbuf.as_mut_ptr(),
-                )
-            })
+            let timer = BlockTimer::default();
+            instrument(
+                span,
+                poll_fn(|cx| {
+                    self.poll_stat(
+                        cx, //
+                        &timer,
+                        &path,
+                        sys::LIBSSH2_SFTP_STAT,
+                        stbuf.as_mut_ptr(),
+                    )
+                }),
+            )
             .await?;
             Ok(FileAttr(stbuf.assume_init()))
         }
@@ -236,33 +268,45 @@ impl<'sess> Sftp<'sess> {
 
     /// Acquire the metadata for a file.
     pub async fn lstat(&mut self, path: impl AsRef<Path>) -> Result<FileAttr> {
+        let span = op_span!(self.sess.borrow(), "lstat", path = %path.as_ref().display());
         let path = path_to_bytes(path.as_ref())?;
         unsafe {
             let mut stbuf = mem::MaybeUninit::zeroed();
-            poll_fn(|cx| {
-                self.poll_stat(
-                    cx, //
-                    &*path,
-                    sys::LIBSSH2_SFTP_LSTAT,
-                    stbuf.as_mut_ptr(),
-                )
-            })
+            let timer = BlockTimer::default();
+            instrument(
+                span,
+                poll_fn(|cx| {
+                    self.poll_stat(
+                        cx, //
+                        &timer,
+                        &path,
+                        sys::LIBSSH2_SFTP_LSTAT,
+                        stbuf.as_mut_ptr(),
+                    )
+                }),
+            )
             .await?;
             Ok(FileAttr(stbuf.assume_init()))
         }
     }
 
     pub async fn setstat(&mut self, path: impl AsRef<Path>, attrs: FileAttr) -> Result<()> {
+        let span = op_span!(self.sess.borrow(), "setstat", path = %path.as_ref().display());
         let path = path_to_bytes(path.as_ref())?;
         let mut attrs = attrs;
-        poll_fn(|cx| {
-            self.poll_stat(
-                cx, //
-                &*path,
-                sys::LIBSSH2_SFTP_SETSTAT,
-                &mut attrs.0,
-            )
-        })
+        let timer = BlockTimer::default();
+        instrument(
+            span,
+            poll_fn(|cx| {
+                self.poll_stat(
+                    cx, //
+                    &timer,
+                    &path,
+                    sys::LIBSSH2_SFTP_SETSTAT,
+                    &mut attrs.0,
+                )
+            }),
+        )
         .await?;
         Ok(())
     }
@@ -270,6 +314,7 @@ impl<'sess> Sftp<'sess> {
     fn poll_open(
         &mut self,
         cx: &mut task::Context<'_>,
+        timer: &BlockTimer,
         path: &[u8],
         options: &OpenOptions,
         open_type: libc::c_int,
@@ -277,7 +322,7 @@ impl<'sess> Sftp<'sess> {
         let sftp = &mut self.raw;
         let flags = options.flags;
         let mode = options.mode;
-        self.sess.poll_with(cx, |sess| {
+        self.sess.borrow_mut().poll_with(cx, timer, |sess| {
             let raw = NonNull::new(unsafe {
                 sys::libssh2_sftp_open_ex(
                     sftp.as_mut(),
@@ -292,29 +337,46 @@ impl<'sess> Sftp<'sess> {
         })
     }
 
-    pub async fn open<'a>(&'a mut self, path: impl AsRef<Path>) -> Result<File<'a, 'sess>> {
+    pub async fn open<'a>(&'a mut self, path: impl AsRef<Path>) -> Result<File<'a>> {
         OpenOptions::new()
             .read(true)
             .open(path.as_ref(), self)
             .await
     }
 
-    pub async fn opendir<'a>(&'a mut self, path: impl AsRef<Path>) -> Result<Dir<'a, 'sess>> {
-        let path = path_to_bytes(path.as_ref())?;
+    pub async fn opendir<'a>(&'a mut self, path: impl AsRef<Path>) -> Result<Dir<'a>> {
+        let path = path.as_ref();
+        let span = op_span!(self.sess.borrow(), "opendir", path = %path.display());
+        let path_bytes = path_to_bytes(path)?;
         let mut options = OpenOptions::new();
         options.read(true);
-        let raw =
-            poll_fn(|cx| self.poll_open(cx, &*path, &options, sys::LIBSSH2_SFTP_OPENDIR)).await?;
-        Ok(Dir(Handle { raw, sftp: self }))
+        let timer = BlockTimer::default();
+        let raw = instrument(
+            span,
+            poll_fn(|cx| {
+                self.poll_open(cx, &timer, &path_bytes, &options, sys::LIBSSH2_SFTP_OPENDIR)
+            }),
+        )
+        .await?;
+        Ok(Dir(Handle {
+            raw,
+            path: path.to_owned(),
+            sftp: self,
+            read_timer: BlockTimer::default(),
+            write_timer: BlockTimer::default(),
+        }))
     }
 }
 
-struct Handle<'a, 'sess> {
+struct Handle<'a> {
     raw: NonNull<sys::LIBSSH2_SFTP_HANDLE>,
-    sftp: &'a mut Sftp<'sess>,
+    path: PathBuf,
+    sftp: &'a mut Sftp,
+    read_timer: BlockTimer,
+    write_timer: BlockTimer,
 }
 
-impl Drop for Handle<'_, '_> {
+impl Drop for Handle<'_> {
     fn drop(&mut self) {
         unsafe {
             // FIXME: should we handle EAGAIN at here?
@@ -323,33 +385,40 @@ impl Drop for Handle<'_, '_> {
     }
 }
 
-impl Handle<'_, '_> {
+impl Handle<'_> {
     fn poll_fstat(
-        &mut self,
+        &self,
         cx: &mut task::Context<'_>,
+        timer: &BlockTimer,
         attrs: *mut sys::LIBSSH2_SFTP_ATTRIBUTES,
         setstat: bool,
     ) -> Poll<Result<()>> {
-        let handle = &mut self.raw;
-        let sftp = &mut self.sftp.raw;
+        let handle = self.raw;
+        let sftp = self.sftp.raw;
         let setstat = if setstat { 1 } else { 0 };
-        self.sftp.sess.poll_with(cx, |_| {
-            let rc = unsafe { sys::libssh2_sftp_fstat_ex(handle.as_mut(), attrs, setstat) };
+        self.sftp.sess.borrow_mut().poll_with(cx, timer, |_| {
+            let rc = unsafe { sys::libssh2_sftp_fstat_ex(handle.as_ptr(), attrs, setstat) };
             match rc {
                 0 => Ok(()),
                 _ => Err(Ssh2Error::from_code(unsafe {
-                    sys::libssh2_sftp_last_error(sftp.as_mut()) as libc::c_int
+                    sys::libssh2_sftp_last_error(sftp.as_ptr()) as libc::c_int
                 })),
             }
         })
     }
 
-    fn poll_read(&mut self, cx: &mut task::Context<'_>, dst: &mut [u8]) -> Poll<Result<usize>> {
-        let handle = &mut self.raw;
-        self.sftp.sess.poll_with(cx, |sess| {
+    fn poll_read(
+        &self,
+        cx: &mut task::Context<'_>,
+        timer: &BlockTimer,
+        dst: &mut [u8],
+    ) -> Poll<Result<usize>> {
+        let _enter = tracing::trace_span!("read", handle = ?self.raw).entered();
+        let handle = self.raw;
+        self.sftp.sess.borrow_mut().poll_with(cx, timer, |sess| {
             sess.rc(unsafe {
                 sys::libssh2_sftp_read(
-                    handle.as_mut(),
+                    handle.as_ptr(),
                     dst.as_mut_ptr() as *mut libc::c_char,
                     dst.len() as libc::size_t,
                 )
@@ -358,12 +427,18 @@ impl Handle<'_, '_> {
         })
     }
 
-    fn poll_write(&mut self, cx: &mut task::Context<'_>, src: &[u8]) -> Poll<Result<usize>> {
-        let handle = &mut self.raw;
-        self.sftp.sess.poll_with(cx, |sess| {
+    fn poll_write(
+        &self,
+        cx: &mut task::Context<'_>,
+        timer: &BlockTimer,
+        src: &[u8],
+    ) -> Poll<Result<usize>> {
+        let _enter = tracing::trace_span!("write", handle = ?self.raw).entered();
+        let handle = self.raw;
+        self.sftp.sess.borrow_mut().poll_with(cx, timer, |sess| {
             sess.rc(unsafe {
                 sys::libssh2_sftp_write(
-                    handle.as_mut(),
+                    handle.as_ptr(),
                     src.as_ptr() as *const libc::c_char,
                     src.len() as libc::size_t,
                 )
@@ -372,17 +447,19 @@ impl Handle<'_, '_> {
         })
     }
 
-    fn poll_fsync(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<()>> {
-        let handle = &mut self.raw;
-        self.sftp.sess.poll_with(cx, |sess| {
-            sess.rc(unsafe { sys::libssh2_sftp_fsync(handle.as_mut()) })
+    fn poll_fsync(&self, cx: &mut task::Context<'_>, timer: &BlockTimer) -> Poll<Result<()>> {
+        let _enter = tracing::trace_span!("fsync", handle = ?self.raw).entered();
+        let handle = self.raw;
+        self.sftp.sess.borrow_mut().poll_with(cx, timer, |sess| {
+            sess.rc(unsafe { sys::libssh2_sftp_fsync(handle.as_ptr()) })
                 .map(drop)
         })
     }
 
     fn poll_readdir(
-        &mut self,
+        &self,
         cx: &mut task::Context<'_>,
+        timer: &BlockTimer,
         pathbuf: &mut Vec<u8>,
         attr: *mut sys::LIBSSH2_SFTP_ATTRIBUTES,
     ) -> Poll<Result<()>> {
@@ -390,11 +467,11 @@ impl Handle<'_, '_> {
             pathbuf.set_len(pathbuf.capacity());
         }
 
-        let handle = &mut self.raw;
-        self.sftp.sess.poll_with(cx, |sess| {
+        let handle = self.raw;
+        self.sftp.sess.borrow_mut().poll_with(cx, timer, |sess| {
             let res = sess.rc(unsafe {
                 sys::libssh2_sftp_readdir_ex(
-                    handle.as_mut(),
+                    handle.as_ptr(),
                     pathbuf.as_mut_ptr() as *mut libc::c_char,
                     pathbuf.len() as libc::size_t,
                     ptr::null_mut(),
@@ -414,68 +491,83 @@ impl Handle<'_, '_> {
 }
 
 /// A file handle corresponding to an SFTP connection.
-pub struct File<'a, 'sess>(Handle<'a, 'sess>);
+pub struct File<'a>(Handle<'a>);
 
 // TODO: AsyncSeek
 
-impl File<'_, '_> {
+impl File<'_> {
     pub async fn stat(&mut self) -> Result<FileAttr> {
+        let span = handle_span!(self.0, "fstat");
         unsafe {
             let mut stbuf = mem::MaybeUninit::zeroed();
-            poll_fn(|cx| self.0.poll_fstat(cx, stbuf.as_mut_ptr(), false)).await?;
+            let timer = BlockTimer::default();
+            instrument(
+                span,
+                poll_fn(|cx| self.0.poll_fstat(cx, &timer, stbuf.as_mut_ptr(), false)),
+            )
+            .await?;
             Ok(FileAttr(stbuf.assume_init()))
         }
     }
 
     pub async fn setstat(&mut self, attrs: FileAttr) -> Result<()> {
+        let span = handle_span!(self.0, "fsetstat");
         let mut attrs = attrs;
-        poll_fn(|cx| self.0.poll_fstat(cx, &mut attrs.0, true)).await?;
+        let timer = BlockTimer::default();
+        instrument(
+            span,
+            poll_fn(|cx| self.0.poll_fstat(cx, &timer, &mut attrs.0, true)),
+        )
+        .await?;
         Ok(())
     }
 
     pub async fn read<'a>(&'a mut self, dst: &'a mut [u8]) -> Result<usize> {
-        poll_fn(|cx| self.0.poll_read(cx, dst)).await
+        let span = handle_span!(self.0, "read");
+        let timer = BlockTimer::default();
+        instrument(span, poll_fn(|cx| self.0.poll_read(cx, &timer, dst))).await
     }
 
     pub async fn write<'a>(&'a mut self, src: &'a [u8]) -> Result<usize> {
-        poll_fn(|cx| self.0.poll_write(cx, src)).await
+        let span = handle_span!(self.0, "write");
+        let timer = BlockTimer::default();
+        instrument(span, poll_fn(|cx| self.0.poll_write(cx, &timer, src))).await
     }
 
     /// Synchronize the file data and metada to the disk on the remote server.
     pub async fn fsync(&mut self) -> Result<()> {
-        poll_fn(|cx| self.0.poll_fsync(cx)).await
+        let span = handle_span!(self.0, "fsync");
+        let timer = BlockTimer::default();
+        instrument(span, poll_fn(|cx| self.0.poll_fsync(cx, &timer))).await
     }
 }
 
-impl AsyncRead for File<'_, '_> {
+impl AsyncRead for File<'_> {
     fn poll_read(
         self: Pin<&mut Self>,
         cx: &mut task::Context<'_>,
         dst: &mut [u8],
     ) -> Poll<io::Result<usize>> {
-        self.get_mut()
-            .0
-            .poll_read(cx, dst)
+        self.0
+            .poll_read(cx, &self.0.read_timer, dst)
             .map_err(|err| err.into_io_error())
     }
 }
 
-impl AsyncWrite for File<'_, '_> {
+impl AsyncWrite for File<'_> {
     fn poll_write(
         self: Pin<&mut Self>,
         cx: &mut task::Context<'_>,
         src: &[u8],
     ) -> Poll<io::Result<usize>> {
-        self.get_mut()
-            .0
-            .poll_write(cx, src)
+        self.0
+            .poll_write(cx, &self.0.write_timer, src)
             .map_err(|err| err.into_io_error())
     }
 
     fn poll_flush(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
-        self.get_mut()
-            .0
-            .poll_fsync(cx)
+        self.0
+            .poll_fsync(cx, &self.0.write_timer)
             .map_err(|err| err.into_io_error())
     }
 
@@ -485,22 +577,34 @@ impl AsyncWrite for File<'_, '_> {
 }
 
 /// A directory handle corresponding to an SFTP connection.
-pub struct Dir<'a, 'sess>(Handle<'a, 'sess>);
+pub struct Dir<'a>(Handle<'a>);
 
-impl Dir<'_, '_> {
+impl Dir<'_> {
     /// Acquire the attribute information of this directory.
     pub async fn stat(&mut self) -> Result<FileAttr> {
+        let span = handle_span!(self.0, "fstat");
         unsafe {
             let mut stbuf = mem::MaybeUninit::zeroed();
-            poll_fn(|cx| self.0.poll_fstat(cx, stbuf.as_mut_ptr(), false)).await?;
+            let timer = BlockTimer::default();
+            instrument(
+                span,
+                poll_fn(|cx| self.0.poll_fstat(cx, &timer, stbuf.as_mut_ptr(), false)),
+            )
+            .await?;
             Ok(FileAttr(stbuf.assume_init()))
         }
     }
 
     /// Set the attribute information of the directory.
     pub async fn setstat(&mut self, attrs: FileAttr) -> Result<()> {
+        let span = handle_span!(self.0, "fsetstat");
         let mut attrs = attrs;
-        poll_fn(|cx| self.0.poll_fstat(cx, &mut attrs.0, true)).await?;
+        let timer = BlockTimer::default();
+        instrument(
+            span,
+            poll_fn(|cx| self.0.poll_fstat(cx, &timer, &mut attrs.0, true)),
+        )
+        .await?;
         Ok(())
     }
 
@@ -511,15 +615,21 @@ impl Dir<'_, '_> {
     }
 
     async fn readdir_inner(&mut self) -> Result<Option<DirEntry>> {
+        let span = handle_span!(self.0, "readdir");
         let mut path = Vec::with_capacity(1024);
         let mut attr = mem::MaybeUninit::zeroed();
-        poll_fn(|cx| {
-            self.0.poll_readdir(
-                cx, //
-                &mut path,
-                attr.as_mut_ptr(),
-            )
-        })
+        let timer = BlockTimer::default();
+        instrument(
+            span,
+            poll_fn(|cx| {
+                self.0.poll_readdir(
+                    cx, //
+                    &timer,
+                    &mut path,
+                    attr.as_mut_ptr(),
+                )
+            }),
+        )
         .await?;
 
         if path.is_empty() {
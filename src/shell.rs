@@ -0,0 +1,266 @@
+//! A persistent shell running the commands one after another.
+
+use crate::{
+    channel::{Channel, ChannelStderr, ChannelStdin, ChannelStdout, ExitStatus},
+    command::quote,
+    error::Result,
+    util::random_marker,
+};
+use std::io;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+
+/// A shell kept open on a channel, in which the commands are run in turn.
+///
+/// The commands are evaluated by the same shell process, so the working
+/// directory, the variables and the functions defined by a command are
+/// visible to the later commands. The end of the output of each command is
+/// detected with the unique markers printed after it, which requires the
+/// login shell of the remote user to be a POSIX shell.
+///
+/// The standard input of each command is redirected from `/dev/null`.
+///
+/// Since the commands run in the shell process itself, a command which
+/// terminates the shell, such as `exit` or a failure after `set -e`, ends
+/// the session. The command and all the later ones fail with an error of
+/// the kind `UnexpectedEof`. Wrap such a command in a subshell `( ... )` to
+/// keep the session alive.
+pub struct ShellSession {
+    channel: Channel,
+    stdin: ChannelStdin,
+    stdout: MarkerReader<ChannelStdout>,
+    stderr: MarkerReader<ChannelStderr>,
+    marker: String,
+    seq: u64,
+}
+
+impl ShellSession {
+    pub(crate) async fn new(mut channel: Channel) -> Result<Self> {
+        channel.shell().await?;
+
+        let (stdin, stdout, stderr) = channel.split();
+        let mut shell = Self {
+            channel,
+            stdin,
+            stdout: MarkerReader::new(stdout),
+            stderr: MarkerReader::new(stderr),
+            marker: random_marker(),
+            seq: 0,
+        };
+
+        // Discard the banners printed by the login scripts.
+        shell.run("true").await?;
+
+        Ok(shell)
+    }
+
+    /// Run the command line in the shell, and collect its output.
+    ///
+    /// The future must be driven to completion. If it is dropped halfway,
+    /// the output of the command is attributed to the next command.
+    pub async fn run(&mut self, command: &str) -> Result<ShellOutput> {
+        if self.stdout.eof || self.stderr.eof {
+            return Err(shell_exited().into());
+        }
+        self.seq += 1;
+        let marker = format!("{}_{}__", self.marker, self.seq);
+
+        // The command is passed through `eval`, so that a syntax error in it
+        // fails the command rather than terminating the shell.
+        let script = format!(
+            "eval {} </dev/null\n\
+             printf '\\n%s %d\\n' {marker} $?\n\
+             printf '\\n%s\\n' {marker} >&2\n",
+            quote(command),
+            marker = marker,
+        );
+        self.stdin.write_all(script.as_bytes()).await?;
+        self.stdin.flush().await?;
+
+        let stdout_marker = format!("\n{} ", marker);
+        let stderr_marker = format!("\n{}\n", marker);
+        let (stdout, stderr) = futures::try_join!(
+            self.stdout.read_until(stdout_marker.as_bytes()),
+            self.stderr.read_until(stderr_marker.as_bytes()),
+        )?;
+        let code = self.stdout.read_exit_code().await?;
+
+        Ok(ShellOutput {
+            code,
+            stdout,
+            stderr,
+        })
+    }
+
+    /// Return the channel on which the shell is running.
+    pub fn channel(&mut self) -> &mut Channel {
+        &mut self.channel
+    }
+
+    /// Close the standard input of the shell, and wait for it to exit.
+    pub async fn exit(mut self) -> Result<ExitStatus> {
+        self.stdin.shutdown().await?;
+        self.channel.wait().await
+    }
+}
+
+/// The output of a command run in a `ShellSession`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShellOutput {
+    /// The exit code of the command.
+    pub code: i32,
+    /// The data written to the standard output.
+    pub stdout: Vec<u8>,
+    /// The data written to the standard error.
+    pub stderr: Vec<u8>,
+}
+
+impl ShellOutput {
+    /// Return whether the command has exited successfully.
+    pub fn success(&self) -> bool {
+        self.code == 0
+    }
+}
+
+/// A reader which keeps the data read past the marker for the next command.
+struct MarkerReader<R> {
+    reader: R,
+    buf: Vec<u8>,
+    eof: bool,
+}
+
+impl<R: AsyncRead + Unpin> MarkerReader<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buf: Vec::new(),
+            eof: false,
+        }
+    }
+
+    /// Read until the marker, and return the data before it.
+    async fn read_until(&mut self, marker: &[u8]) -> io::Result<Vec<u8>> {
+        let mut pos = 0;
+        loop {
+            if let Some(i) = find(&self.buf[pos..], marker) {
+                let start = pos + i;
+                let data = self.buf[..start].to_vec();
+                self.buf.drain(..start + marker.len());
+                return Ok(data);
+            }
+            pos = self.buf.len().saturating_sub(marker.len() - 1);
+            self.fill().await?;
+        }
+    }
+
+    /// Read the exit code printed after the marker on the standard output.
+    async fn read_exit_code(&mut self) -> io::Result<i32> {
+        let line = self.read_until(b"\n").await?;
+        String::from_utf8_lossy(&line)
+            .trim()
+            .parse()
+            .map_err(|_| invalid_data("invalid exit code from the shell"))
+    }
+
+    async fn fill(&mut self) -> io::Result<()> {
+        let mut chunk = [0u8; 8192];
+        let n = self.reader.read(&mut chunk).await?;
+        if n == 0 {
+            self.eof = true;
+            return Err(shell_exited());
+        }
+        self.buf.extend_from_slice(&chunk[..n]);
+        Ok(())
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn shell_exited() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "the shell has exited, e.g. by `exit` or `set -e` in a command",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::task::{self, Poll};
+    use std::{collections::VecDeque, pin::Pin};
+
+    /// A reader which returns one chunk per read.
+    struct ChunkReader(VecDeque<&'static [u8]>);
+
+    impl AsyncRead for ChunkReader {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _: &mut task::Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            let chunk = self.0.pop_front().unwrap_or_default();
+            buf[..chunk.len()].copy_from_slice(chunk);
+            Poll::Ready(Ok(chunk.len()))
+        }
+    }
+
+    fn reader(chunks: &[&'static [u8]]) -> MarkerReader<ChunkReader> {
+        MarkerReader::new(ChunkReader(chunks.iter().copied().collect()))
+    }
+
+    #[tokio::test]
+    async fn read_until_marker() {
+        let mut reader = reader(&[b"hello\n\nM_1__ 0\n"]);
+        assert_eq!(reader.read_until(b"\nM_1__ ").await.unwrap(), b"hello\n");
+        assert_eq!(reader.read_exit_code().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn marker_across_chunks() {
+        let mut reader = reader(&[b"out\n\nM_", b"1", b"__ 4", b"2\n"]);
+        assert_eq!(reader.read_until(b"\nM_1__ ").await.unwrap(), b"out\n");
+        assert_eq!(reader.read_exit_code().await.unwrap(), 42);
+    }
+
+    #[tokio::test]
+    async fn keep_data_after_marker() {
+        let mut reader = reader(&[b"a\nM_1__ 1\nb\nM_2__ 127\n"]);
+        assert_eq!(reader.read_until(b"\nM_1__ ").await.unwrap(), b"a");
+        assert_eq!(reader.read_exit_code().await.unwrap(), 1);
+        assert_eq!(reader.read_until(b"\nM_2__ ").await.unwrap(), b"b");
+        assert_eq!(reader.read_exit_code().await.unwrap(), 127);
+    }
+
+    #[tokio::test]
+    async fn ignore_marker_of_other_command() {
+        let mut reader = reader(&[b"\nM_1__x\n\nM_11__ 0\n\nM_1__ 3\n"]);
+        assert_eq!(
+            reader.read_until(b"\nM_1__ ").await.unwrap(),
+            b"\nM_1__x\n\nM_11__ 0\n"
+        );
+        assert_eq!(reader.read_exit_code().await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn invalid_exit_code() {
+        let mut reader = reader(&[b"\nM_1__ oops\n"]);
+        reader.read_until(b"\nM_1__ ").await.unwrap();
+        let err = reader.read_exit_code().await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn shell_exited_before_marker() {
+        let mut reader = reader(&[b"logout\n"]);
+        let err = reader.read_until(b"\nM_1__ ").await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(reader.eof);
+    }
+}
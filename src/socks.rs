@@ -0,0 +1,385 @@
+//! The server side of the SOCKS4a and SOCKS5 protocols.
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const SOCKS4_VERSION: u8 = 4;
+const SOCKS5_VERSION: u8 = 5;
+
+const CMD_CONNECT: u8 = 1;
+
+const SOCKS4_REPLY_VERSION: u8 = 0;
+const SOCKS4_GRANTED: u8 = 0x5a;
+const SOCKS4_REJECTED: u8 = 0x5b;
+
+const SOCKS5_NO_AUTH: u8 = 0x00;
+const SOCKS5_NO_ACCEPTABLE_METHODS: u8 = 0xff;
+
+const SOCKS5_ATYP_IPV4: u8 = 1;
+const SOCKS5_ATYP_DOMAIN: u8 = 3;
+const SOCKS5_ATYP_IPV6: u8 = 4;
+
+const SOCKS5_SUCCEEDED: u8 = 0;
+const SOCKS5_GENERAL_FAILURE: u8 = 1;
+const SOCKS5_COMMAND_NOT_SUPPORTED: u8 = 7;
+const SOCKS5_ADDRESS_TYPE_NOT_SUPPORTED: u8 = 8;
+
+/// The maximum length of the user ID and the domain name in SOCKS4a requests.
+const SOCKS4_MAX_STRING_LEN: usize = 255;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Version {
+    V4,
+    V5,
+}
+
+/// A CONNECT request received from a SOCKS client.
+#[derive(Debug)]
+pub(crate) struct Request {
+    pub(crate) version: Version,
+    pub(crate) host: String,
+    pub(crate) port: u16,
+}
+
+/// Read a CONNECT request from the client.
+///
+/// The unsupported requests are rejected before returning an error.
+pub(crate) async fn accept<S>(stream: &mut S) -> io::Result<Request>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    match stream.read_u8().await? {
+        SOCKS4_VERSION => accept_v4(stream).await,
+        SOCKS5_VERSION => accept_v5(stream).await,
+        version => Err(invalid_data(format!(
+            "unsupported SOCKS version: {}",
+            version
+        ))),
+    }
+}
+
+/// Notify the client whether the connection to the destination has been established.
+pub(crate) async fn reply<S>(stream: &mut S, version: Version, success: bool) -> io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    match version {
+        Version::V4 => {
+            let status = if success {
+                SOCKS4_GRANTED
+            } else {
+                SOCKS4_REJECTED
+            };
+            reply_v4(stream, status).await
+        }
+        Version::V5 => {
+            let status = if success {
+                SOCKS5_SUCCEEDED
+            } else {
+                SOCKS5_GENERAL_FAILURE
+            };
+            reply_v5(stream, status).await
+        }
+    }
+}
+
+async fn accept_v4<S>(stream: &mut S) -> io::Result<Request>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let command = stream.read_u8().await?;
+    let port = stream.read_u16().await?;
+    let mut ip = [0u8; 4];
+    stream.read_exact(&mut ip).await?;
+    // The user ID is not used, and may not be UTF-8.
+    read_nul_terminated(stream).await?;
+
+    if command != CMD_CONNECT {
+        reply_v4(stream, SOCKS4_REJECTED).await?;
+        return Err(invalid_data(format!(
+            "unsupported SOCKS command: {}",
+            command
+        )));
+    }
+
+    // SOCKS4a: the address 0.0.0.x indicates that the domain name follows.
+    let host = match ip {
+        [0, 0, 0, x] if x != 0 => String::from_utf8(read_nul_terminated(stream).await?)
+            .map_err(|_| invalid_data("invalid domain name"))?,
+        ip => Ipv4Addr::from(ip).to_string(),
+    };
+
+    Ok(Request {
+        version: Version::V4,
+        host,
+        port,
+    })
+}
+
+async fn accept_v5<S>(stream: &mut S) -> io::Result<Request>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let nmethods = stream.read_u8().await?;
+    let mut methods = vec![0u8; nmethods as usize];
+    stream.read_exact(&mut methods).await?;
+    if !methods.contains(&SOCKS5_NO_AUTH) {
+        stream
+            .write_all(&[SOCKS5_VERSION, SOCKS5_NO_ACCEPTABLE_METHODS])
+            .await?;
+        return Err(invalid_data("no acceptable SOCKS authentication methods"));
+    }
+    stream.write_all(&[SOCKS5_VERSION, SOCKS5_NO_AUTH]).await?;
+
+    let mut header = [0u8; 4];
+    stream.read_exact(&mut header).await?;
+    let [version, command, _reserved, atyp] = header;
+    if version != SOCKS5_VERSION {
+        return Err(invalid_data(format!(
+            "unsupported SOCKS version: {}",
+            version
+        )));
+    }
+
+    let host = match atyp {
+        SOCKS5_ATYP_IPV4 => {
+            let mut ip = [0u8; 4];
+            stream.read_exact(&mut ip).await?;
+            Ipv4Addr::from(ip).to_string()
+        }
+        SOCKS5_ATYP_IPV6 => {
+            let mut ip = [0u8; 16];
+            stream.read_exact(&mut ip).await?;
+            Ipv6Addr::from(ip).to_string()
+        }
+        SOCKS5_ATYP_DOMAIN => {
+            let len = stream.read_u8().await?;
+            let mut domain = vec![0u8; len as usize];
+            stream.read_exact(&mut domain).await?;
+            String::from_utf8(domain).map_err(|_| invalid_data("invalid domain name"))?
+        }
+        atyp => {
+            reply_v5(stream, SOCKS5_ADDRESS_TYPE_NOT_SUPPORTED).await?;
+            return Err(invalid_data(format!(
+                "unsupported SOCKS address type: {}",
+                atyp
+            )));
+        }
+    };
+    let port = stream.read_u16().await?;
+
+    if command != CMD_CONNECT {
+        reply_v5(stream, SOCKS5_COMMAND_NOT_SUPPORTED).await?;
+        return Err(invalid_data(format!(
+            "unsupported SOCKS command: {}",
+            command
+        )));
+    }
+
+    Ok(Request {
+        version: Version::V5,
+        host,
+        port,
+    })
+}
+
+async fn reply_v4<S>(stream: &mut S, status: u8) -> io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    // The bound address is ignored by the clients.
+    stream
+        .write_all(&[SOCKS4_REPLY_VERSION, status, 0, 0, 0, 0, 0, 0])
+        .await
+}
+
+async fn reply_v5<S>(stream: &mut S, status: u8) -> io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    // The bound address is reported as 0.0.0.0:0, since the connection is
+    // established by the remote host.
+    stream
+        .write_all(&[
+            SOCKS5_VERSION,
+            status,
+            0,
+            SOCKS5_ATYP_IPV4,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        ])
+        .await
+}
+
+async fn read_nul_terminated<S>(stream: &mut S) -> io::Result<Vec<u8>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut buf = Vec::new();
+    loop {
+        match stream.read_u8().await? {
+            0 => break,
+            b if buf.len() < SOCKS4_MAX_STRING_LEN => buf.push(b),
+            _ => return Err(invalid_data("too long string in SOCKS4 request")),
+        }
+    }
+    Ok(buf)
+}
+
+fn invalid_data(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    /// A stream which reads the request from the buffer, and records the replies.
+    struct MockStream {
+        input: std::io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl AsyncRead for MockStream {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            Pin::new(&mut self.input).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncWrite for MockStream {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            Pin::new(&mut self.output).poll_write(cx, buf)
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.output).poll_flush(cx)
+        }
+
+        fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.output).poll_shutdown(cx)
+        }
+    }
+
+    async fn accept_request(input: &[u8]) -> (io::Result<Request>, Vec<u8>) {
+        let mut stream = MockStream {
+            input: std::io::Cursor::new(input.to_vec()),
+            output: Vec::new(),
+        };
+        let res = accept(&mut stream).await;
+        (res, stream.output)
+    }
+
+    #[tokio::test]
+    async fn socks4_connect() {
+        let (res, output) = accept_request(b"\x04\x01\x00\x50\x0a\x00\x00\x01user\x00").await;
+        let request = res.unwrap();
+        assert_eq!(request.version, Version::V4);
+        assert_eq!(request.host, "10.0.0.1");
+        assert_eq!(request.port, 80);
+        assert!(output.is_empty());
+    }
+
+    #[tokio::test]
+    async fn socks4a_connect() {
+        let (res, _) = accept_request(b"\x04\x01\x01\xbb\x00\x00\x00\x01\x00example.com\x00").await;
+        let request = res.unwrap();
+        assert_eq!(request.host, "example.com");
+        assert_eq!(request.port, 443);
+    }
+
+    #[tokio::test]
+    async fn socks4_non_utf8_user_id() {
+        let (res, _) =
+            accept_request(b"\x04\x01\x00\x50\x00\x00\x00\x01\xff\xfe\x00example.com\x00").await;
+        assert_eq!(res.unwrap().host, "example.com");
+    }
+
+    #[tokio::test]
+    async fn socks4a_non_utf8_host() {
+        let (res, _) = accept_request(b"\x04\x01\x00\x50\x00\x00\x00\x01\x00\xff\xfe\x00").await;
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn socks4_too_long_user_id() {
+        let mut input = vec![4, 1, 0, 80, 10, 0, 0, 1];
+        input.resize(input.len() + SOCKS4_MAX_STRING_LEN + 1, b'a');
+        input.push(0);
+        let (res, _) = accept_request(&input).await;
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn socks4_reject_bind() {
+        let (res, output) = accept_request(b"\x04\x02\x00\x50\x0a\x00\x00\x01\x00").await;
+        assert!(res.is_err());
+        assert_eq!(
+            output,
+            [SOCKS4_REPLY_VERSION, SOCKS4_REJECTED, 0, 0, 0, 0, 0, 0]
+        );
+    }
+
+    #[tokio::test]
+    async fn socks5_connect_domain() {
+        let (res, output) =
+            accept_request(b"\x05\x01\x00\x05\x01\x00\x03\x0bexample.com\x00\x50").await;
+        let request = res.unwrap();
+        assert_eq!(request.version, Version::V5);
+        assert_eq!(request.host, "example.com");
+        assert_eq!(request.port, 80);
+        assert_eq!(output, [SOCKS5_VERSION, SOCKS5_NO_AUTH]);
+    }
+
+    #[tokio::test]
+    async fn socks5_connect_ipv6() {
+        let (res, _) = accept_request(
+            b"\x05\x01\x00\x05\x01\x00\x04\
+              \x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x01\x00\x16",
+        )
+        .await;
+        let request = res.unwrap();
+        assert_eq!(request.host, "::1");
+        assert_eq!(request.port, 22);
+    }
+
+    #[tokio::test]
+    async fn socks5_no_acceptable_methods() {
+        let (res, output) = accept_request(b"\x05\x01\x02").await;
+        assert!(res.is_err());
+        assert_eq!(output, [SOCKS5_VERSION, SOCKS5_NO_ACCEPTABLE_METHODS]);
+    }
+
+    #[tokio::test]
+    async fn socks5_unsupported_address_type() {
+        let (res, output) = accept_request(b"\x05\x01\x00\x05\x01\x00\x02").await;
+        assert!(res.is_err());
+        assert_eq!(output[..2], [SOCKS5_VERSION, SOCKS5_NO_AUTH]);
+        assert_eq!(
+            output[2..4],
+            [SOCKS5_VERSION, SOCKS5_ADDRESS_TYPE_NOT_SUPPORTED]
+        );
+    }
+
+    #[tokio::test]
+    async fn unsupported_version() {
+        let (res, output) = accept_request(b"\x03").await;
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert!(output.is_empty());
+    }
+}
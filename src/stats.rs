@@ -0,0 +1,83 @@
+use std::time::Duration;
+
+/// A snapshot of the traffic statistics of a session or a channel.
+///
+/// For a session, the bytes are counted on the socket, so they include the
+/// key exchange, the authentication, the encryption and the protocol
+/// overhead as well as the payload. For a channel, only the payload read
+/// from and written to the channel is counted.
+///
+/// The reads and writes are the calls that transferred any data, i.e. the
+/// `recv`/`send` calls on the socket for a session, and the read/write
+/// calls on the channel for a channel. They are not SSH packets: the
+/// number of packets is not available, since libssh2 does not expose its
+/// packet sequence numbers, and its transport trace is only compiled into
+/// debug builds.
+///
+/// The blocked time is accounted per pending operation, from when it first
+/// waits for the socket until it completes. For a session, it is summed
+/// over all the operations, so it may exceed the elapsed time when multiple
+/// operations wait concurrently. For a channel, only the operations on the
+/// channel are accounted.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stats {
+    bytes_sent: u64,
+    bytes_received: u64,
+    writes: u64,
+    reads: u64,
+    read_blocked: Duration,
+    write_blocked: Duration,
+}
+
+impl Stats {
+    /// Return the number of bytes sent to the remote host.
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent
+    }
+
+    /// Return the number of bytes received from the remote host.
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes_received
+    }
+
+    /// Return the number of the writes that sent any data.
+    pub fn writes(&self) -> u64 {
+        self.writes
+    }
+
+    /// Return the number of the reads that received any data.
+    pub fn reads(&self) -> u64 {
+        self.reads
+    }
+
+    /// Return the total time the operations spent waiting for the socket to
+    /// become readable.
+    pub fn read_blocked(&self) -> Duration {
+        self.read_blocked
+    }
+
+    /// Return the total time the operations spent waiting for the socket to
+    /// become writable.
+    pub fn write_blocked(&self) -> Duration {
+        self.write_blocked
+    }
+
+    pub(crate) fn record_sent(&mut self, n: usize) {
+        if n > 0 {
+            self.bytes_sent += n as u64;
+            self.writes += 1;
+        }
+    }
+
+    pub(crate) fn record_received(&mut self, n: usize) {
+        if n > 0 {
+            self.bytes_received += n as u64;
+            self.reads += 1;
+        }
+    }
+
+    pub(crate) fn record_blocked(&mut self, read: Duration, write: Duration) {
+        self.read_blocked += read;
+        self.write_blocked += write;
+    }
+}
@@ -0,0 +1,507 @@
+//! Bridging of the local terminal to a remote pseudo-terminal.
+
+use crate::{
+    channel::{copy_streams, Channel, ExitStatus},
+    error::Result,
+    pty::PtySize,
+};
+use futures::{
+    future::{self, Either},
+    task::{self, Poll},
+};
+use mio::{unix::EventedFd, Evented, PollOpt, Ready, Token};
+use std::{io, mem, os::unix::io::RawFd, pin::Pin};
+use tokio::{
+    io::{AsyncRead, PollEvented},
+    signal::unix::{signal, Signal, SignalKind},
+};
+
+/// A guard which keeps the terminal in the raw mode, and restores the
+/// original mode when dropped.
+pub struct RawMode {
+    fd: RawFd,
+    original: libc::termios,
+}
+
+impl RawMode {
+    /// Put the terminal referred by the file descriptor into the raw mode.
+    pub fn enable(fd: RawFd) -> io::Result<Self> {
+        unsafe {
+            let mut original = mem::zeroed::<libc::termios>();
+            if libc::tcgetattr(fd, &mut original) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let mut raw = original;
+            libc::cfmakeraw(&mut raw);
+            if libc::tcsetattr(fd, libc::TCSANOW, &raw) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(Self { fd, original })
+        }
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetattr(self.fd, libc::TCSANOW, &self.original);
+        }
+    }
+}
+
+/// Return the dimensions of the terminal referred by the file descriptor.
+pub fn size(fd: RawFd) -> io::Result<PtySize> {
+    unsafe {
+        let mut winsize = mem::zeroed::<libc::winsize>();
+        if libc::ioctl(fd, libc::TIOCGWINSZ, &mut winsize) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(PtySize {
+            cols: winsize.ws_col as u32,
+            rows: winsize.ws_row as u32,
+            pixel_width: winsize.ws_xpixel as u32,
+            pixel_height: winsize.ws_ypixel as u32,
+        })
+    }
+}
+
+/// The standard input of the current process, polled by the reactor.
+///
+/// The standard input of Tokio is read on the blocking thread pool, and a
+/// pending read cannot be cancelled, so the runtime could not shut down
+/// until the next key is pressed. It is read in the non-blocking mode
+/// instead. The inputs which cannot be polled, such as regular files, are
+/// read on the blocking thread pool as usual, since their reads do not wait.
+pub(crate) enum Stdin {
+    Evented(PollEvented<NonBlocking>),
+    Blocking(tokio::io::Stdin),
+}
+
+impl Stdin {
+    fn new(fd: RawFd) -> Self {
+        match NonBlocking::new(fd).and_then(PollEvented::new) {
+            Ok(io) => Stdin::Evented(io),
+            Err(err) => {
+                tracing::debug!("fd {} cannot be polled: {}", fd, err);
+                Stdin::Blocking(tokio::io::stdin())
+            }
+        }
+    }
+}
+
+/// Return the standard input of the current process.
+pub(crate) fn stdin() -> Stdin {
+    Stdin::new(libc::STDIN_FILENO)
+}
+
+impl AsyncRead for Stdin {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stdin::Evented(io) => Pin::new(io).poll_read(cx, buf),
+            Stdin::Blocking(io) => Pin::new(io).poll_read(cx, buf),
+        }
+    }
+}
+
+/// A file descriptor in the non-blocking mode.
+///
+/// `O_NONBLOCK` is a flag of the open file description, which is shared
+/// with the parent shell, and on a terminal with the standard output and
+/// error as well. A terminal is therefore reopened by its name, so that the
+/// flag does not leak to the other streams, where e.g. `println!` would
+/// fail with `EAGAIN`.
+///
+/// The other file descriptors, such as pipes, are switched to the
+/// non-blocking mode in place, and the original flags are restored when
+/// dropped, including on unwinding from a panic. If the process is killed
+/// or aborts before that, the file description is left in the
+/// non-blocking mode.
+pub(crate) struct NonBlocking {
+    fd: RawFd,
+    /// The original flags to restore, or `None` if the file descriptor is
+    /// reopened and owned.
+    flags: Option<libc::c_int>,
+}
+
+impl NonBlocking {
+    fn new(fd: RawFd) -> io::Result<Self> {
+        unsafe {
+            if libc::isatty(fd) == 1 {
+                let mut name = [0 as libc::c_char; 256];
+                let rc = libc::ttyname_r(fd, name.as_mut_ptr(), name.len());
+                if rc != 0 {
+                    return Err(io::Error::from_raw_os_error(rc));
+                }
+                let flags = libc::O_RDONLY | libc::O_NOCTTY | libc::O_NONBLOCK | libc::O_CLOEXEC;
+                let fd = libc::open(name.as_ptr(), flags);
+                if fd < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                return Ok(Self { fd, flags: None });
+            }
+
+            let flags = libc::fcntl(fd, libc::F_GETFL);
+            if flags < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Self {
+                fd,
+                flags: Some(flags),
+            })
+        }
+    }
+}
+
+impl Drop for NonBlocking {
+    fn drop(&mut self) {
+        unsafe {
+            match self.flags {
+                Some(flags) => libc::fcntl(self.fd, libc::F_SETFL, flags),
+                None => libc::close(self.fd),
+            };
+        }
+    }
+}
+
+impl io::Read for NonBlocking {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = unsafe { libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(n as usize)
+    }
+}
+
+impl Evented for NonBlocking {
+    fn register(
+        &self,
+        poll: &mio::Poll,
+        token: Token,
+        interest: Ready,
+        opts: PollOpt,
+    ) -> io::Result<()> {
+        EventedFd(&self.fd).register(poll, token, interest, opts)
+    }
+
+    fn reregister(
+        &self,
+        poll: &mio::Poll,
+        token: Token,
+        interest: Ready,
+        opts: PollOpt,
+    ) -> io::Result<()> {
+        EventedFd(&self.fd).reregister(poll, token, interest, opts)
+    }
+
+    fn deregister(&self, poll: &mio::Poll) -> io::Result<()> {
+        EventedFd(&self.fd).deregister(poll)
+    }
+}
+
+/// A stream of the changes of the terminal size, driven by `SIGWINCH`.
+struct SizeChanges {
+    fd: RawFd,
+    signal: Signal,
+    last: Option<PtySize>,
+}
+
+impl SizeChanges {
+    fn new(fd: RawFd) -> io::Result<Self> {
+        Ok(Self {
+            fd,
+            signal: signal(SignalKind::window_change())?,
+            last: size(fd).ok(),
+        })
+    }
+
+    /// Wait for the terminal to be resized, and return the new size.
+    ///
+    /// The signals which do not change the size are ignored, and so are the
+    /// ones received while the file descriptor is not a terminal.
+    async fn recv(&mut self) -> Option<PtySize> {
+        loop {
+            self.signal.recv().await?;
+            if let Ok(size) = size(self.fd) {
+                if self.last != Some(size) {
+                    self.last = Some(size);
+                    return Some(size);
+                }
+            }
+        }
+    }
+}
+
+/// Connect the standard streams of the current process to the channel, and
+/// wait for the remote process to exit.
+///
+/// The channel is expected to have a pseudo-terminal allocated with the
+/// size of the local terminal, and a shell or a command started on it. The
+/// local terminal is put into the raw mode until this function returns, and
+/// the changes of its size are sent to the remote host on `SIGWINCH`. If the
+/// standard input is not a terminal, it is forwarded as is, and EOF is sent
+/// when it ends.
+///
+/// The outputs are flushed as they arrive. The standard input is not read
+/// after this function returns, so the runtime can be shut down while the
+/// terminal is idle.
+pub async fn interact(channel: &mut Channel) -> Result<ExitStatus> {
+    let raw_mode = if unsafe { libc::isatty(libc::STDIN_FILENO) } == 1 {
+        Some(RawMode::enable(libc::STDIN_FILENO)?)
+    } else {
+        None
+    };
+
+    let mut size_changes = SizeChanges::new(libc::STDOUT_FILENO)?;
+    let (mut remote_stdin, mut remote_stdout, mut remote_stderr) = channel.split();
+
+    // Each direction is copied concurrently, so that the remote outputs are
+    // drained while the input waits for the channel window, and vice versa.
+    {
+        let streams = copy_streams(
+            Some((stdin(), &mut remote_stdin)),
+            Some((&mut remote_stdout, tokio::io::stdout())),
+            Some((&mut remote_stderr, tokio::io::stderr())),
+        );
+        let resizes = async {
+            while let Some(size) = size_changes.recv().await {
+                channel
+                    .resize(size.cols, size.rows, size.pixel_width, size.pixel_height)
+                    .await?;
+            }
+            future::pending::<Result<()>>().await
+        };
+        futures::pin_mut!(streams, resizes);
+        match future::select(streams, resizes).await {
+            Either::Left((res, _)) => res.map(drop)?,
+            Either::Right((res, _)) => res?,
+        }
+    }
+
+    drop(raw_mode);
+    channel.wait().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{ptr, time::Duration};
+    use tokio::{io::AsyncReadExt, time::timeout};
+
+    /// A pair of the master and slave file descriptors of a pseudo-terminal.
+    struct Pty {
+        master: RawFd,
+        slave: RawFd,
+    }
+
+    impl Pty {
+        fn open(cols: u16, rows: u16) -> Self {
+            let winsize = libc::winsize {
+                ws_col: cols,
+                ws_row: rows,
+                ws_xpixel: 0,
+                ws_ypixel: 0,
+            };
+            let (mut master, mut slave) = (-1, -1);
+            let res = unsafe {
+                libc::openpty(
+                    &mut master,
+                    &mut slave,
+                    ptr::null_mut(),
+                    ptr::null(),
+                    &winsize,
+                )
+            };
+            assert_eq!(res, 0, "openpty: {}", io::Error::last_os_error());
+            Self { master, slave }
+        }
+
+        fn resize(&self, cols: u16, rows: u16) {
+            let winsize = libc::winsize {
+                ws_col: cols,
+                ws_row: rows,
+                ws_xpixel: 0,
+                ws_ypixel: 0,
+            };
+            let res = unsafe { libc::ioctl(self.master, libc::TIOCSWINSZ, &winsize) };
+            assert_eq!(res, 0, "TIOCSWINSZ: {}", io::Error::last_os_error());
+        }
+    }
+
+    impl Drop for Pty {
+        fn drop(&mut self) {
+            unsafe {
+                libc::close(self.slave);
+                libc::close(self.master);
+            }
+        }
+    }
+
+    fn termios(fd: RawFd) -> libc::termios {
+        unsafe {
+            let mut termios = mem::zeroed::<libc::termios>();
+            assert_eq!(libc::tcgetattr(fd, &mut termios), 0);
+            termios
+        }
+    }
+
+    fn file_flags(fd: RawFd) -> libc::c_int {
+        unsafe { libc::fcntl(fd, libc::F_GETFL) }
+    }
+
+    #[test]
+    fn raw_mode_is_restored() {
+        let pty = Pty::open(80, 24);
+        let original = termios(pty.slave);
+        assert_ne!(original.c_lflag & libc::ICANON, 0);
+        assert_ne!(original.c_lflag & libc::ECHO, 0);
+
+        let raw_mode = RawMode::enable(pty.slave).unwrap();
+        let raw = termios(pty.slave);
+        assert_eq!(raw.c_lflag & (libc::ICANON | libc::ECHO | libc::ISIG), 0);
+        assert_eq!(raw.c_oflag & libc::OPOST, 0);
+
+        drop(raw_mode);
+        let restored = termios(pty.slave);
+        assert_eq!(restored.c_lflag, original.c_lflag);
+        assert_eq!(restored.c_iflag, original.c_iflag);
+        assert_eq!(restored.c_oflag, original.c_oflag);
+    }
+
+    #[test]
+    fn raw_mode_requires_terminal() {
+        let file = std::fs::File::open(std::env::current_exe().unwrap()).unwrap();
+        let err = RawMode::enable(std::os::unix::io::AsRawFd::as_raw_fd(&file))
+            .err()
+            .unwrap();
+        assert_eq!(err.raw_os_error(), Some(libc::ENOTTY));
+    }
+
+    #[test]
+    fn terminal_size() {
+        let pty = Pty::open(132, 43);
+        let size = size(pty.slave).unwrap();
+        assert_eq!((size.cols, size.rows), (132, 43));
+    }
+
+    #[tokio::test]
+    async fn resize_on_sigwinch() {
+        let pty = Pty::open(80, 24);
+        let mut size_changes = SizeChanges::new(pty.slave).unwrap();
+
+        pty.resize(100, 40);
+        unsafe { libc::raise(libc::SIGWINCH) };
+        let size = timeout(Duration::from_secs(5), size_changes.recv())
+            .await
+            .expect("the resize is not notified")
+            .unwrap();
+        assert_eq!((size.cols, size.rows), (100, 40));
+
+        // The signal without a change of the size is ignored.
+        unsafe { libc::raise(libc::SIGWINCH) };
+        assert!(timeout(Duration::from_millis(100), size_changes.recv())
+            .await
+            .is_err());
+    }
+
+    /// Write the data to the file descriptor at once.
+    fn write_fd(fd: RawFd, data: &[u8]) {
+        let n = unsafe { libc::write(fd, data.as_ptr() as *const libc::c_void, data.len()) };
+        assert_eq!(n, data.len() as isize);
+    }
+
+    #[tokio::test]
+    async fn read_terminal_without_blocking() {
+        let pty = Pty::open(80, 24);
+        let original = file_flags(pty.slave);
+
+        // The terminal is reopened, so the shared flags are left untouched.
+        let mut stdin = Stdin::new(pty.slave);
+        assert!(matches!(stdin, Stdin::Evented(..)));
+        assert_eq!(file_flags(pty.slave), original);
+
+        // The pending read is cancelled without leaving a blocked thread,
+        // so the runtime shuts down at the end of the test.
+        let mut buf = [0u8; 16];
+        assert!(timeout(Duration::from_millis(50), stdin.read(&mut buf))
+            .await
+            .is_err());
+
+        write_fd(pty.master, b"hello\n");
+        let n = timeout(Duration::from_secs(5), stdin.read(&mut buf))
+            .await
+            .expect("the input is not read")
+            .unwrap();
+        assert_eq!(&buf[..n], b"hello\n");
+    }
+
+    #[tokio::test]
+    async fn read_pipe_without_blocking() {
+        let mut fds = [-1; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let [reader, writer] = fds;
+        let original = file_flags(reader);
+
+        let mut stdin = Stdin::new(reader);
+        assert!(matches!(stdin, Stdin::Evented(..)));
+        assert_ne!(file_flags(reader) & libc::O_NONBLOCK, 0);
+
+        let mut buf = [0u8; 16];
+        assert!(timeout(Duration::from_millis(50), stdin.read(&mut buf))
+            .await
+            .is_err());
+        write_fd(writer, b"hello");
+        unsafe { libc::close(writer) };
+        let mut input = Vec::new();
+        timeout(Duration::from_secs(5), stdin.read_to_end(&mut input))
+            .await
+            .expect("the input is not read")
+            .unwrap();
+        assert_eq!(input, b"hello");
+
+        drop(stdin);
+        assert_eq!(file_flags(reader), original);
+        unsafe { libc::close(reader) };
+    }
+
+    #[test]
+    fn restore_flags_on_panic() {
+        let mut fds = [-1; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let [reader, writer] = fds;
+        let original = file_flags(reader);
+
+        let res = std::panic::catch_unwind(|| {
+            let _fd = NonBlocking::new(reader).unwrap();
+            assert_ne!(file_flags(reader) & libc::O_NONBLOCK, 0);
+            panic!("interrupted");
+        });
+        assert!(res.is_err());
+        assert_eq!(file_flags(reader), original);
+
+        unsafe {
+            libc::close(reader);
+            libc::close(writer);
+        }
+    }
+
+    #[tokio::test]
+    async fn fall_back_to_blocking_stream() {
+        let file = std::fs::File::open(std::env::current_exe().unwrap()).unwrap();
+        let fd = std::os::unix::io::AsRawFd::as_raw_fd(&file);
+        let original = file_flags(fd);
+
+        let stdin = Stdin::new(fd);
+        assert!(matches!(stdin, Stdin::Blocking(..)));
+        assert_eq!(file_flags(fd), original);
+    }
+}
@@ -69,3 +69,13 @@ pub(crate) fn bytes_to_path(bytes: Vec<u8>) -> crate::Result<PathBuf> {
         .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
     Ok(PathBuf::from(path))
 }
+
+/// Generate a random marker which hardly appears in the output of the
+/// remote processes by accident.
+pub(crate) fn random_marker() -> String {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+
+    let hasher = RandomState::new().build_hasher();
+    format!("__TOKIO_LIBSSH2_{:016x}", hasher.finish())
+}